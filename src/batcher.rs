@@ -12,20 +12,27 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::handler::{PRODUCE_CONSUME, VERSION};
+use crate::handler::PRODUCE_CONSUME;
 use crate::pc_error::PCError;
 use cylinder::Signer;
 use rand::Rng;
 use transact::protocol::batch::{BatchBuilder, BatchPair};
 use transact::protocol::transaction::{HashMethod, TransactionBuilder, TransactionPair};
 
+/// Payload bytes of a transaction with its input and output addresses
+pub(crate) type TxnPayload = (Vec<u8>, Vec<Vec<u8>>, Vec<Vec<u8>>);
+
 pub(crate) struct Batcher {
     signer: Box<dyn Signer>,
+    family_version: String,
 }
 
 impl Batcher {
-    pub(crate) fn new(signer: Box<dyn Signer>) -> Batcher {
-        Batcher { signer }
+    pub(crate) fn new(signer: Box<dyn Signer>, family_version: &str) -> Batcher {
+        Batcher {
+            signer,
+            family_version: family_version.to_string(),
+        }
     }
 
     pub(crate) fn single_txn(
@@ -35,7 +42,18 @@ impl Batcher {
         outputs: Vec<Vec<u8>>,
     ) -> Result<BatchPair, PCError> {
         let txn = self.get_txn(raw_bytes, inputs, outputs)?;
-        self.get_batch(txn)
+        self.get_batch(vec![txn])
+    }
+
+    /// Builds a batch of one transaction per payload, in order. The batch
+    /// is committed atomically, if any transaction is invalid none is
+    /// applied.
+    pub(crate) fn multi_txn(&self, payloads: Vec<TxnPayload>) -> Result<BatchPair, PCError> {
+        let mut txns = vec![];
+        for (raw_bytes, inputs, outputs) in payloads {
+            txns.push(self.get_txn(&raw_bytes, inputs, outputs)?);
+        }
+        self.get_batch(txns)
     }
 
     pub(crate) fn get_txn(
@@ -44,18 +62,21 @@ impl Batcher {
         inputs: Vec<Vec<u8>>,
         outputs: Vec<Vec<u8>>,
     ) -> Result<TransactionPair, PCError> {
-        //        let nonce = rand::thread_rng()
-        //            .gen_iter::<u8>()
-        //            .take(64)
-        //            .collect::<Vec<u8>>();
-        let nonce = b"arun_nonce".to_vec();
+        // A random nonce gives identical payloads distinct transaction ids.
+        // The header keeps the nonce as a string, so it is written in hex.
+        let nonce = hex::encode(
+            rand::thread_rng()
+                .gen_iter::<u8>()
+                .take(64)
+                .collect::<Vec<u8>>(),
+        );
         match TransactionBuilder::new()
             .with_batcher_public_key(self.signer.public_key().unwrap().into_bytes())
             .with_family_name(PRODUCE_CONSUME.to_string())
-            .with_family_version(VERSION.to_string())
+            .with_family_version(self.family_version.clone())
             .with_inputs(inputs)
             .with_outputs(outputs)
-            .with_nonce(nonce)
+            .with_nonce(nonce.into_bytes())
             .with_payload_hash_method(HashMethod::SHA512)
             .with_payload(raw_bytes.to_vec())
             .build_pair(&*self.signer)
@@ -65,11 +86,18 @@ impl Batcher {
         }
     }
 
-    pub(crate) fn get_batch(&self, transaction: TransactionPair) -> Result<BatchPair, PCError> {
-        // Transactions from transaction pair
-        // TODO: Currently works with only one transaction
-        let (txn, _header) = transaction.take();
-        let txns = vec![txn];
+    pub(crate) fn get_batch(
+        &self,
+        transactions: Vec<TransactionPair>,
+    ) -> Result<BatchPair, PCError> {
+        if transactions.is_empty() {
+            return Err(PCError::from("A batch needs at least one transaction"));
+        }
+        // Transactions from transaction pairs
+        let txns = transactions
+            .into_iter()
+            .map(|transaction| transaction.take().0)
+            .collect::<Vec<_>>();
         match BatchBuilder::new()
             .with_transactions(txns)
             .build_pair(&*self.signer)
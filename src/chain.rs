@@ -0,0 +1,362 @@
+// Copyright 2019 Arun S M
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::file_store::{append_record, read_records};
+use crate::handler::STATE_FORMAT;
+use crate::pc_error::PCError;
+use crate::pc_state::PCState;
+use crate::proto::block::Block;
+use crate::scheduler::schedule;
+use crate::state_handler::commit_state;
+use protobuf::RepeatedField;
+use sha2::{Digest, Sha512};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use transact::protocol::batch::Batch;
+use transact::protos::{FromBytes, IntoBytes};
+use transact::state::merkle::MerkleState;
+
+const CHAIN_FILE_NAME: &str = "chain";
+
+/// Chain of blocks, one per committed batch. Each block links to the
+/// previous one by its identifier, so that any change to an earlier block
+/// is detected when the chain is verified.
+pub(crate) struct Chain {
+    path: Option<PathBuf>,
+    blocks: Vec<Block>,
+    /// Header signatures of the committed batches and their transactions
+    committed_ids: HashSet<String>,
+}
+
+impl Chain {
+    pub(crate) fn open(data_dir: Option<PathBuf>) -> Result<Chain, PCError> {
+        let path = data_dir.map(|dir| dir.join(CHAIN_FILE_NAME));
+        let blocks = match &path {
+            Some(file) => read_records::<Block>(file)?,
+            None => vec![],
+        };
+        let mut committed_ids = HashSet::new();
+        for block in &blocks {
+            committed_ids.insert(block.get_batch_id().to_string());
+            committed_ids.extend(block.get_transaction_ids().iter().cloned());
+        }
+        Ok(Chain {
+            path,
+            blocks,
+            committed_ids,
+        })
+    }
+
+    /// Rejects a batch that was already committed, or that carries a
+    /// transaction committed before or twice in the batch.
+    pub(crate) fn check_replay(&self, batch: &Batch) -> Result<(), PCError> {
+        if self.committed_ids.contains(batch.header_signature()) {
+            return Err(PCError::from(format!(
+                "Batch {} was already committed",
+                batch.header_signature()
+            )));
+        }
+        let mut batch_ids = HashSet::new();
+        for txn in batch.transactions() {
+            let txn_id = txn.header_signature();
+            if self.committed_ids.contains(txn_id) || !batch_ids.insert(txn_id) {
+                return Err(PCError::from(format!(
+                    "Transaction {} was already committed",
+                    txn_id
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Wraps a committed batch in a block on top of the chain.
+    pub(crate) fn append(
+        &mut self,
+        batch: Batch,
+        parent_state_root: &str,
+        state_root: &str,
+    ) -> Result<(), PCError> {
+        let mut block = Block::new();
+        block.set_block_num(self.blocks.len() as u64);
+        if let Some(previous) = self.blocks.last() {
+            block.set_previous_block_id(previous.get_block_id().to_string());
+        }
+        block.set_parent_state_root(parent_state_root.to_string());
+        block.set_state_root(state_root.to_string());
+        block.set_batch_id(batch.header_signature().to_string());
+        block.set_transaction_ids(RepeatedField::from_vec(
+            batch
+                .transactions()
+                .iter()
+                .map(|txn| txn.header_signature().to_string())
+                .collect(),
+        ));
+        self.committed_ids.insert(block.get_batch_id().to_string());
+        self.committed_ids
+            .extend(block.get_transaction_ids().iter().cloned());
+        let batch_bytes = match batch.into_bytes() {
+            Ok(bytes) => bytes,
+            Err(err) => return Err(PCError::from(err.to_string())),
+        };
+        block.set_batch(batch_bytes);
+        block.set_state_format(STATE_FORMAT);
+        block.set_block_id(compute_block_id(&block));
+
+        if let Some(path) = &self.path {
+            append_record(path, &block)?;
+        }
+        self.blocks.push(block);
+        Ok(())
+    }
+
+    pub(crate) fn get_blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+}
+
+/// Walks the chain from the first block, checking the links between blocks,
+/// the block identifiers and the batches they carry, and that every block
+/// is applied on a state root recorded before it. Every batch is replayed
+/// on its parent state root and must result in the recorded state root.
+/// Returns the number of verified blocks.
+pub(crate) fn verify_chain(
+    chain: &Chain,
+    genesis_root: &str,
+    state: &PCState,
+    statestore: &MerkleState,
+) -> Result<usize, PCError> {
+    let mut previous_block_id = String::new();
+    let mut known_roots = HashSet::new();
+    known_roots.insert(genesis_root.to_string());
+
+    for (block_num, block) in chain.get_blocks().iter().enumerate() {
+        if block.get_block_num() != block_num as u64 {
+            return Err(PCError::from(format!(
+                "Block {} is out of order",
+                block.get_block_num()
+            )));
+        }
+        if block.get_previous_block_id() != previous_block_id {
+            return Err(PCError::from(format!(
+                "Block {} does not link to the previous block",
+                block_num
+            )));
+        }
+        if block.get_block_id() != compute_block_id(block) {
+            return Err(PCError::from(format!(
+                "Block {} does not match its identifier",
+                block_num
+            )));
+        }
+        if !known_roots.contains(block.get_parent_state_root()) {
+            return Err(PCError::from(format!(
+                "Block {} is applied on an unknown state root",
+                block_num
+            )));
+        }
+
+        let batch = match Batch::from_bytes(block.get_batch()) {
+            Ok(batch) => batch,
+            Err(err) => return Err(PCError::from(err.to_string())),
+        };
+        let transaction_ids: Vec<String> = batch
+            .transactions()
+            .iter()
+            .map(|txn| txn.header_signature().to_string())
+            .collect();
+        if batch.header_signature() != block.get_batch_id()
+            || transaction_ids.as_slice() != block.get_transaction_ids()
+        {
+            return Err(PCError::from(format!(
+                "Block {} does not match its batch",
+                block_num
+            )));
+        }
+
+        if block.get_state_format() != STATE_FORMAT {
+            return Err(PCError::from(format!(
+                "Block {} has the state format {}, the handler writes {}",
+                block_num,
+                block.get_state_format(),
+                STATE_FORMAT
+            )));
+        }
+
+        // Replay the batch, committing it again only writes nodes that
+        // are already in the database
+        let batch_pair = match batch.into_pair() {
+            Ok(batch_pair) => batch_pair,
+            Err(err) => return Err(PCError::from(err.to_string())),
+        };
+        let result = match schedule(state, vec![batch_pair], block.get_parent_state_root())?.pop() {
+            Some(result) => result,
+            None => return Err(PCError::from("Unexpected error, no result found")),
+        };
+        let replayed_root = commit_state(statestore, block.get_parent_state_root(), result)?;
+        if replayed_root != block.get_state_root() {
+            return Err(PCError::from(format!(
+                "Block {} does not result in its state root",
+                block_num
+            )));
+        }
+
+        known_roots.insert(block.get_state_root().to_string());
+        previous_block_id = block.get_block_id().to_string();
+    }
+
+    Ok(chain.get_blocks().len())
+}
+
+fn compute_block_id(block: &Block) -> String {
+    let mut sha = Sha512::default();
+    sha.input(block.get_block_num().to_be_bytes());
+    sha.input(block.get_previous_block_id());
+    sha.input(block.get_parent_state_root());
+    sha.input(block.get_state_root());
+    sha.input(block.get_batch_id());
+    for transaction_id in block.get_transaction_ids() {
+        sha.input(transaction_id);
+    }
+    sha.input(block.get_batch());
+    sha.input(block.get_state_format().to_be_bytes());
+    hex::encode(sha.result())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batcher::Batcher;
+    use crate::config::{PCConfig, SchedulerMode};
+    use crate::handler::VERSION_2;
+    use crate::payload::ProduceConsumePayload;
+    use crate::transaction::encode_payload;
+    use cylinder::secp256k1::Secp256k1Context;
+    use cylinder::Context;
+    use transact::state::merkle::MerkleRadixTree;
+
+    /// Commits every line as a batch on a fresh state, wrapping each in a
+    /// block. Returns the chain with the state and the genesis root.
+    fn committed_chain(lines: &[&str]) -> (Chain, PCState, String) {
+        let state = PCState::new(&PCConfig::in_memory(SchedulerMode::Serial)).unwrap();
+        let statestore = MerkleState::new(state.get_db());
+        let genesis_root = MerkleRadixTree::new(state.get_db(), None)
+            .unwrap()
+            .get_merkle_root();
+
+        let context = Secp256k1Context::new();
+        let signer = context.new_signer(context.new_random_private_key());
+        let batcher = Batcher::new(signer, VERSION_2);
+        let mut chain = Chain::open(None).unwrap();
+        let mut state_root = genesis_root.clone();
+        for line in lines {
+            let payload = ProduceConsumePayload::new(line).unwrap();
+            let (payload, inputs, outputs) =
+                encode_payload(&payload, None, VERSION_2, false).unwrap();
+            let batch = batcher.single_txn(&payload, inputs, outputs).unwrap();
+            let result = schedule(&state, vec![batch.clone()], &state_root)
+                .unwrap()
+                .pop()
+                .unwrap();
+            let new_state_root = commit_state(&statestore, &state_root, result).unwrap();
+            chain
+                .append(batch.batch().clone(), &state_root, &new_state_root)
+                .unwrap();
+            state_root = new_state_root;
+        }
+        (chain, state, genesis_root)
+    }
+
+    #[test]
+    fn verify_chain_detects_a_tampered_block() {
+        let (chain, state, genesis_root) =
+            committed_chain(&["CREATE_ITEM apple kg", "PRODUCE apple 4"]);
+        let statestore = MerkleState::new(state.get_db());
+        assert_eq!(
+            verify_chain(&chain, &genesis_root, &state, &statestore).unwrap(),
+            2
+        );
+
+        let failure = |blocks: Vec<Block>| {
+            let tampered = Chain {
+                path: None,
+                blocks,
+                committed_ids: HashSet::new(),
+            };
+            verify_chain(&tampered, &genesis_root, &state, &statestore)
+                .unwrap_err()
+                .to_string()
+        };
+
+        // A rewritten state root no longer matches the block identifier
+        let mut blocks = chain.get_blocks().to_vec();
+        blocks[0].set_state_root(genesis_root.clone());
+        assert!(failure(blocks).contains("Block 0 does not match its identifier"));
+
+        // A rewritten link is detected even with a recomputed identifier
+        let mut blocks = chain.get_blocks().to_vec();
+        blocks[1].set_previous_block_id(String::new());
+        let block_id = compute_block_id(&blocks[1]);
+        blocks[1].set_block_id(block_id);
+        assert!(failure(blocks).contains("Block 1 does not link to the previous block"));
+
+        // A state root the batch does not result in fails the replay
+        let mut blocks = chain.get_blocks().to_vec();
+        let other_root = blocks[0].get_state_root().to_string();
+        blocks[1].set_state_root(other_root);
+        let block_id = compute_block_id(&blocks[1]);
+        blocks[1].set_block_id(block_id);
+        assert!(failure(blocks).contains("Block 1 does not result in its state root"));
+
+        // A batch swapped for another one does not match the block
+        let mut blocks = chain.get_blocks().to_vec();
+        let other_batch = blocks[1].get_batch().to_vec();
+        blocks[0].set_batch(other_batch);
+        let block_id = compute_block_id(&blocks[0]);
+        blocks[0].set_block_id(block_id);
+        assert!(failure(blocks).contains("Block 0 does not match its batch"));
+    }
+
+    #[test]
+    fn check_replay_rejects_committed_batches_and_transactions() {
+        let (chain, _, _) = committed_chain(&["CREATE_ITEM apple kg", "PRODUCE apple 4"]);
+        let batch = Batch::from_bytes(chain.get_blocks()[1].get_batch()).unwrap();
+        let err = chain.check_replay(&batch).unwrap_err();
+        assert!(err.to_string().contains("Batch"));
+
+        // A committed transaction wrapped in a new batch is rejected too
+        let context = Secp256k1Context::new();
+        let signer = context.new_signer(context.new_random_private_key());
+        let batcher = Batcher::new(signer, VERSION_2);
+        let txn = batch.transactions()[0].clone().into_pair().unwrap();
+        let rewrapped = batcher.get_batch(vec![txn]).unwrap();
+        let err = chain.check_replay(rewrapped.batch()).unwrap_err();
+        assert!(err.to_string().contains("Transaction"));
+
+        // The same command signed again gets a new nonce, once per batch
+        let payload = ProduceConsumePayload::new("PRODUCE apple 4").unwrap();
+        let (payload, inputs, outputs) = encode_payload(&payload, None, VERSION_2, false).unwrap();
+        let txn = batcher.get_txn(&payload, inputs, outputs).unwrap().take().0;
+        let fresh = batcher
+            .get_batch(vec![txn.clone().into_pair().unwrap()])
+            .unwrap();
+        assert!(chain.check_replay(fresh.batch()).is_ok());
+        let doubled = batcher
+            .get_batch(vec![
+                txn.clone().into_pair().unwrap(),
+                txn.into_pair().unwrap(),
+            ])
+            .unwrap();
+        assert!(chain.check_replay(doubled.batch()).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+// Copyright 2019 Arun S M
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::export::ExportFormat;
+use crate::pc_error::PCError;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+const EXPORT_USAGE: &str = "Please input \"EXPORT <json|csv> <file> [state root]\"";
+
+/// A line entered by the user. Commands that change the state are
+/// submitted as transactions, the rest are served locally.
+#[derive(Debug)]
+pub(crate) enum UserCommand {
+    /// PRODUCE or CONSUME, the line is parsed into the payload later
+    Transaction(String),
+    /// Print the journal of committed state roots
+    History,
+    /// Switch the working state root to the journal entry at a height
+    Checkout(u64),
+    /// Re-walk the block chain and check it was not tampered with
+    Verify,
+    /// Read the quantity of an item at the working state root, optionally
+    /// the item owned by a public key
+    Query {
+        identifier: String,
+        owner: Option<String>,
+    },
+    /// Print a page of the items at the working state root, optionally only
+    /// the items owned by a public key
+    List { page: usize, owner: Option<String> },
+    /// Write the items at a state root, the working one by default, to a file
+    Export {
+        format: ExportFormat,
+        path: PathBuf,
+        state_root: Option<String>,
+    },
+    /// Produce the initial inventory read from a CSV file
+    Import(PathBuf),
+    /// Submit the lines of a file as the transactions of a single batch
+    Batch(PathBuf),
+    /// Submit every line of a file as its own batch, in a single run of the
+    /// scheduler
+    Submit(PathBuf),
+    /// Write the merkle proof of an item at a state root to a file
+    Prove {
+        identifier: String,
+        path: PathBuf,
+        state_root: Option<String>,
+    },
+    /// Check a merkle proof read from a file against a state root
+    VerifyProof {
+        path: PathBuf,
+        state_root: Option<String>,
+    },
+    /// Print the items that changed between two state roots, the second
+    /// one being the working root by default
+    Diff {
+        from_root: String,
+        to_root: Option<String>,
+    },
+}
+
+impl UserCommand {
+    pub(crate) fn parse(text: &str) -> Result<UserCommand, PCError> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        match words.first() {
+            Some(&"HISTORY") => Ok(UserCommand::History),
+            Some(&"VERIFY") => Ok(UserCommand::Verify),
+            Some(&"QUERY") => match (words.get(1), words.get(2)) {
+                (Some(identifier), owner) if words.len() <= 3 => Ok(UserCommand::Query {
+                    identifier: identifier.to_string(),
+                    owner: owner.map(|owner| owner.to_string()),
+                }),
+                _ => Err(PCError::from("Please input \"QUERY <identifier> [owner]\"")),
+            },
+            Some(&"LIST") => {
+                let page = match words.get(1).map(|page| usize::from_str(page)) {
+                    None => 1,
+                    Some(Ok(page)) if page > 0 && words.len() <= 3 => page,
+                    _ => return Err(PCError::from("Please input \"LIST [page] [owner]\"")),
+                };
+                Ok(UserCommand::List {
+                    page,
+                    owner: words.get(2).map(|owner| owner.to_string()),
+                })
+            }
+            Some(&"EXPORT") => {
+                let format = match words.get(1) {
+                    Some(&"json") => ExportFormat::Json,
+                    Some(&"csv") => ExportFormat::Csv,
+                    _ => return Err(PCError::from(EXPORT_USAGE)),
+                };
+                match (words.get(2), words.get(3), words.len()) {
+                    (Some(path), state_root, 3) | (Some(path), state_root, 4) => {
+                        Ok(UserCommand::Export {
+                            format,
+                            path: PathBuf::from(path),
+                            state_root: state_root.map(|root| root.to_string()),
+                        })
+                    }
+                    _ => Err(PCError::from(EXPORT_USAGE)),
+                }
+            }
+            Some(&"CHECKOUT") => match words.get(1).map(|height| u64::from_str(height)) {
+                Some(Ok(height)) => Ok(UserCommand::Checkout(height)),
+                _ => Err(PCError::from("Please input \"CHECKOUT <height>\"")),
+            },
+            Some(&"IMPORT") => match words.get(1) {
+                Some(path) if words.len() == 2 => Ok(UserCommand::Import(PathBuf::from(path))),
+                _ => Err(PCError::from("Please input \"IMPORT <file>\"")),
+            },
+            Some(&"BATCH") => match words.get(1) {
+                Some(path) if words.len() == 2 => Ok(UserCommand::Batch(PathBuf::from(path))),
+                _ => Err(PCError::from("Please input \"BATCH <file>\"")),
+            },
+            Some(&"SUBMIT") => match words.get(1) {
+                Some(path) if words.len() == 2 => Ok(UserCommand::Submit(PathBuf::from(path))),
+                _ => Err(PCError::from("Please input \"SUBMIT <file>\"")),
+            },
+            Some(&"PROVE") => match (words.get(1), words.get(2), words.get(3)) {
+                (Some(identifier), Some(path), state_root) if words.len() <= 4 => {
+                    Ok(UserCommand::Prove {
+                        identifier: identifier.to_string(),
+                        path: PathBuf::from(path),
+                        state_root: state_root.map(|root| root.to_string()),
+                    })
+                }
+                _ => Err(PCError::from(
+                    "Please input \"PROVE <identifier> <file> [state root]\"",
+                )),
+            },
+            Some(&"VERIFY_PROOF") => match (words.get(1), words.get(2)) {
+                (Some(path), state_root) if words.len() <= 3 => Ok(UserCommand::VerifyProof {
+                    path: PathBuf::from(path),
+                    state_root: state_root.map(|root| root.to_string()),
+                }),
+                _ => Err(PCError::from(
+                    "Please input \"VERIFY_PROOF <file> [state root]\"",
+                )),
+            },
+            Some(&"DIFF") => match (words.get(1), words.get(2)) {
+                (Some(from_root), to_root) if words.len() <= 3 => Ok(UserCommand::Diff {
+                    from_root: from_root.to_string(),
+                    to_root: to_root.map(|root| root.to_string()),
+                }),
+                _ => Err(PCError::from(
+                    "Please input \"DIFF <state root> [state root]\"",
+                )),
+            },
+            _ => Ok(UserCommand::Transaction(text.to_string())),
+        }
+    }
+}
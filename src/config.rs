@@ -0,0 +1,170 @@
+// Copyright 2019 Arun S M
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::handler::{VERSION, VERSION_2};
+use crate::pc_error::PCError;
+use std::env;
+use std::path::PathBuf;
+
+const USAGE: &str = "Usage: pc-transact [--data-dir <directory>] \
+                     [--database <memory|lmdb|sqlite>] [--key-file <file>] [--owner-scoped] \
+                     [--family-version <1.0|2.0>] [--delete-empty] \
+                     [--scheduler <serial|parallel>]";
+
+/// The key-value store backing the merkle state.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum DatabaseKind {
+    Memory,
+    Lmdb,
+    Sqlite,
+}
+
+/// How the transactions of a batch are scheduled for execution.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum SchedulerMode {
+    /// One transaction after the other, in the order of the batch
+    Serial,
+    /// Transactions whose declared inputs and outputs do not conflict are
+    /// executed concurrently
+    Parallel,
+}
+
+/// Startup options for the application, read from the command line.
+#[derive(Debug, Default)]
+pub(crate) struct PCConfig {
+    data_dir: Option<PathBuf>,
+    database: Option<DatabaseKind>,
+    key_file: Option<PathBuf>,
+    owner_scoped: bool,
+    family_version: Option<String>,
+    delete_empty: bool,
+    scheduler: Option<SchedulerMode>,
+}
+
+impl PCConfig {
+    pub(crate) fn from_args() -> Result<PCConfig, PCError> {
+        let mut config = PCConfig::default();
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--data-dir" => {
+                    let value = match args.next() {
+                        Some(value) => value,
+                        None => return Err(PCError::from(USAGE)),
+                    };
+                    config.data_dir = Some(PathBuf::from(value));
+                }
+                "--database" => {
+                    let kind = match args.next().as_deref() {
+                        Some("memory") => DatabaseKind::Memory,
+                        Some("lmdb") => DatabaseKind::Lmdb,
+                        Some("sqlite") => DatabaseKind::Sqlite,
+                        _ => return Err(PCError::from(USAGE)),
+                    };
+                    config.database = Some(kind);
+                }
+                "--key-file" => {
+                    let value = match args.next() {
+                        Some(value) => value,
+                        None => return Err(PCError::from(USAGE)),
+                    };
+                    config.key_file = Some(PathBuf::from(value));
+                }
+                "--owner-scoped" => config.owner_scoped = true,
+                "--delete-empty" => config.delete_empty = true,
+                "--scheduler" => {
+                    let mode = match args.next().as_deref() {
+                        Some("serial") => SchedulerMode::Serial,
+                        Some("parallel") => SchedulerMode::Parallel,
+                        _ => return Err(PCError::from(USAGE)),
+                    };
+                    config.scheduler = Some(mode);
+                }
+                "--family-version" => match args.next() {
+                    Some(version) if version == VERSION || version == VERSION_2 => {
+                        config.family_version = Some(version)
+                    }
+                    _ => return Err(PCError::from(USAGE)),
+                },
+                _ => return Err(PCError::from(USAGE)),
+            }
+        }
+        if config.owner_scoped && config.key_file.is_none() {
+            return Err(PCError::from(
+                "Owner-scoped items need a --key-file to keep the owner's key",
+            ));
+        }
+        if config.data_dir.is_none() && config.get_database() != DatabaseKind::Memory {
+            return Err(PCError::from(
+                "The database needs a --data-dir to store its files",
+            ));
+        }
+        Ok(config)
+    }
+
+    /// In-memory state scheduled in the mode, with the other defaults
+    #[cfg(test)]
+    pub(crate) fn in_memory(scheduler: SchedulerMode) -> PCConfig {
+        PCConfig {
+            scheduler: Some(scheduler),
+            ..PCConfig::default()
+        }
+    }
+
+    /// Directory where the state is persisted, in-memory state is used
+    /// when this is not set.
+    pub(crate) fn get_data_dir(&self) -> Option<PathBuf> {
+        self.data_dir.clone()
+    }
+
+    /// Database to use, LMDB is the default when a data directory is given.
+    pub(crate) fn get_database(&self) -> DatabaseKind {
+        match (self.database, &self.data_dir) {
+            (Some(kind), _) => kind,
+            (None, Some(_)) => DatabaseKind::Lmdb,
+            (None, None) => DatabaseKind::Memory,
+        }
+    }
+
+    /// File with the signer's private key, a random key is used for the
+    /// run when this is not set.
+    pub(crate) fn get_key_file(&self) -> Option<PathBuf> {
+        self.key_file.clone()
+    }
+
+    /// Whether the items are owned by the signer rather than shared.
+    pub(crate) fn is_owner_scoped(&self) -> bool {
+        self.owner_scoped
+    }
+
+    /// Family version of the submitted transactions, the latest by default.
+    pub(crate) fn get_family_version(&self) -> String {
+        match &self.family_version {
+            Some(version) => version.clone(),
+            None => VERSION_2.to_string(),
+        }
+    }
+
+    /// Items consumed down to nothing are deleted from the state, rather
+    /// than kept with no quantity
+    pub(crate) fn is_delete_empty(&self) -> bool {
+        self.delete_empty
+    }
+
+    /// Scheduler of the batches, serial by default. Both modes result in
+    /// the same state roots.
+    pub(crate) fn get_scheduler_mode(&self) -> SchedulerMode {
+        self.scheduler.unwrap_or(SchedulerMode::Serial)
+    }
+}
@@ -0,0 +1,58 @@
+// Copyright 2019 Arun S M
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::item_state::ItemState;
+use crate::pc_error::PCError;
+use crate::query::read_items;
+use std::collections::BTreeMap;
+use transact::database::Database;
+
+/// Change of a single item between two state roots.
+#[derive(Debug)]
+pub(crate) enum ItemChange {
+    Added(ItemState),
+    Removed(ItemState),
+    Changed { old: ItemState, new: ItemState },
+}
+
+/// Compares the items at two state roots of the same database, returning
+/// the changes ordered by address. Items with the same value are left out.
+pub(crate) fn diff_states(
+    db: Box<dyn Database>,
+    from_root: &str,
+    to_root: &str,
+) -> Result<Vec<ItemChange>, PCError> {
+    let mut from_items: BTreeMap<String, ItemState> =
+        read_items(db.clone(), from_root)?.into_iter().collect();
+    let to_items = read_items(db, to_root)?;
+
+    let mut changes = BTreeMap::new();
+    for (address, new) in to_items {
+        match from_items.remove(&address) {
+            Some(old) => {
+                if old != new {
+                    changes.insert(address, ItemChange::Changed { old, new });
+                }
+            }
+            None => {
+                changes.insert(address, ItemChange::Added(new));
+            }
+        }
+    }
+    // Whatever is left was not found at the second root
+    for (address, old) in from_items {
+        changes.insert(address, ItemChange::Removed(old));
+    }
+    Ok(changes.into_values().collect())
+}
@@ -0,0 +1,107 @@
+// Copyright 2019 Arun S M
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::item_state::ItemState;
+use crate::pc_error::PCError;
+use crate::quantity::format_decimal;
+use crate::query::read_items;
+use std::fs;
+use std::path::Path;
+use transact::database::Database;
+
+/// File formats the inventory can be exported to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ExportFormat {
+    Json,
+    Csv,
+}
+
+/// Writes the inventory at the state root to a file, one record per item
+/// with its identifier, owner, available and reserved quantities,
+/// description and address. Returns the number of records.
+pub(crate) fn export_items(
+    db: Box<dyn Database>,
+    state_root: &str,
+    format: ExportFormat,
+    path: &Path,
+) -> Result<usize, PCError> {
+    let items = read_items(db, state_root)?;
+    let content = match format {
+        ExportFormat::Json => to_json(state_root, &items),
+        ExportFormat::Csv => to_csv(&items),
+    };
+    match fs::write(path, content) {
+        Ok(_) => Ok(items.len()),
+        Err(err) => Err(PCError::from(err.to_string())),
+    }
+}
+
+fn to_json(state_root: &str, items: &[(String, ItemState)]) -> String {
+    let records = items
+        .iter()
+        .map(|(address, item)| {
+            format!(
+                "    {{\"identifier\": \"{}\", \"owner\": \"{}\", \"quantity\": {}, \"reserved\": {}, \"description\": \"{}\", \"address\": \"{}\"}}",
+                escape_json(&item.get_identifier()),
+                item.get_owner(),
+                format_decimal(item.get_quantity(), item.get_scale()),
+                format_decimal(item.get_reserved(), item.get_scale()),
+                escape_json(&item.get_description()),
+                address
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",\n");
+    format!(
+        "{{\n  \"state_root\": \"{}\",\n  \"items\": [\n{}\n  ]\n}}\n",
+        state_root, records
+    )
+}
+
+fn to_csv(items: &[(String, ItemState)]) -> String {
+    let mut content = String::from("identifier,owner,quantity,reserved,description,address\n");
+    for (address, item) in items {
+        content.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            escape_csv(&item.get_identifier()),
+            item.get_owner(),
+            format_decimal(item.get_quantity(), item.get_scale()),
+            format_decimal(item.get_reserved(), item.get_scale()),
+            escape_csv(&item.get_description()),
+            address
+        ));
+    }
+    content
+}
+
+fn escape_json(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn escape_csv(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
@@ -0,0 +1,207 @@
+// Copyright 2019 Arun S M
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::pc_error::PCError;
+use std::convert::TryFrom;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+/// Length of the size written before every record of a record file
+const RECORD_LENGTH_SIZE: usize = 4;
+
+/// Reads a protobuf message stored in a file, `None` if the file is absent.
+pub(crate) fn read_message<T>(path: &Path) -> Result<Option<T>, PCError>
+where
+    T: protobuf::Message,
+{
+    if !path.exists() {
+        return Ok(None);
+    }
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => return Err(PCError::from(err.to_string())),
+    };
+    match T::parse_from_bytes(&bytes) {
+        Ok(message) => Ok(Some(message)),
+        Err(err) => Err(PCError::from(err.to_string())),
+    }
+}
+
+/// Stores a protobuf message in a file.
+pub(crate) fn write_message<T>(path: &Path, message: &T) -> Result<(), PCError>
+where
+    T: protobuf::Message,
+{
+    let bytes = match message.write_to_bytes() {
+        Ok(bytes) => bytes,
+        Err(err) => return Err(PCError::from(err.to_string())),
+    };
+    write_file(path, &bytes)
+}
+
+/// Reads the protobuf records appended to a file, none if the file is
+/// absent. A record cut short by a crash while it was appended is dropped
+/// from the file, so that the next record follows the complete ones.
+pub(crate) fn read_records<T>(path: &Path) -> Result<Vec<T>, PCError>
+where
+    T: protobuf::Message,
+{
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => return Err(PCError::from(err.to_string())),
+    };
+
+    let mut records = vec![];
+    let mut offset = 0;
+    while bytes.len() - offset >= RECORD_LENGTH_SIZE {
+        let mut length: [u8; RECORD_LENGTH_SIZE] = [0; RECORD_LENGTH_SIZE];
+        length.copy_from_slice(&bytes[offset..offset + RECORD_LENGTH_SIZE]);
+        let start = offset + RECORD_LENGTH_SIZE;
+        let end = start + u32::from_be_bytes(length) as usize;
+        if end > bytes.len() {
+            break;
+        }
+        match T::parse_from_bytes(&bytes[start..end]) {
+            Ok(record) => records.push(record),
+            Err(err) => return Err(PCError::from(err.to_string())),
+        }
+        offset = end;
+    }
+
+    if offset < bytes.len() {
+        warn!("Dropping an incomplete record at the end of {:?}", path);
+        let truncated = OpenOptions::new()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_len(offset as u64).and_then(|_| file.sync_all()));
+        if let Err(err) = truncated {
+            return Err(PCError::from(err.to_string()));
+        }
+    }
+    Ok(records)
+}
+
+/// Appends a protobuf record to a file, after its length as a big-endian
+/// u32. The file is synced before returning.
+pub(crate) fn append_record<T>(path: &Path, record: &T) -> Result<(), PCError>
+where
+    T: protobuf::Message,
+{
+    let bytes = match record.write_to_bytes() {
+        Ok(bytes) => bytes,
+        Err(err) => return Err(PCError::from(err.to_string())),
+    };
+    let length = match u32::try_from(bytes.len()) {
+        Ok(length) => length,
+        Err(err) => return Err(PCError::from(err.to_string())),
+    };
+    let mut content = length.to_be_bytes().to_vec();
+    content.extend(bytes);
+    let appended = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(&content).and_then(|_| file.sync_all()));
+    match appended {
+        Ok(_) => Ok(()),
+        Err(err) => Err(PCError::from(err.to_string())),
+    }
+}
+
+/// Replaces the file in one step, so that a crash never leaves a partially
+/// written file behind. The new content is synced before it replaces the
+/// file.
+pub(crate) fn write_file(path: &Path, bytes: &[u8]) -> Result<(), PCError> {
+    let tmp_path = path.with_extension("tmp");
+    let written = fs::File::create(&tmp_path)
+        .and_then(|mut file| file.write_all(bytes).and_then(|_| file.sync_all()));
+    if let Err(err) = written {
+        return Err(PCError::from(err.to_string()));
+    }
+    match fs::rename(&tmp_path, path) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(PCError::from(err.to_string())),
+    }
+}
+
+/// Replaces the file in one step like `write_file`, for secrets such as a
+/// private key. The file is created readable by its owner only.
+pub(crate) fn write_private_file(path: &Path, bytes: &[u8]) -> Result<(), PCError> {
+    let tmp_path = path.with_extension("tmp");
+    // A leftover temporary file would keep its permissions
+    if tmp_path.exists() {
+        if let Err(err) = fs::remove_file(&tmp_path) {
+            return Err(PCError::from(err.to_string()));
+        }
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let written = options
+        .open(&tmp_path)
+        .and_then(|mut file| file.write_all(bytes).and_then(|_| file.sync_all()));
+    if let Err(err) = written {
+        return Err(PCError::from(err.to_string()));
+    }
+    match fs::rename(&tmp_path, path) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(PCError::from(err.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::journal::JournalEntry;
+
+    fn entry(height: u64) -> JournalEntry {
+        let mut entry = JournalEntry::new();
+        entry.set_height(height);
+        entry.set_state_root(format!("root{}", height));
+        entry
+    }
+
+    #[test]
+    fn read_records_drops_an_incomplete_record() {
+        let path = std::env::temp_dir().join(format!("pc-records-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        append_record(&path, &entry(0)).unwrap();
+        append_record(&path, &entry(1)).unwrap();
+        let complete = fs::metadata(&path).unwrap().len();
+
+        // A crash while appending leaves part of the record behind
+        let mut torn = 20u32.to_be_bytes().to_vec();
+        torn.extend(b"root");
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(&torn))
+            .unwrap();
+
+        let records = read_records::<JournalEntry>(&path).unwrap();
+        assert_eq!(records, vec![entry(0), entry(1)]);
+        assert_eq!(fs::metadata(&path).unwrap().len(), complete);
+        append_record(&path, &entry(2)).unwrap();
+        let records = read_records::<JournalEntry>(&path).unwrap();
+        assert_eq!(records, vec![entry(0), entry(1), entry(2)]);
+        fs::remove_file(&path).unwrap();
+    }
+}
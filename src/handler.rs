@@ -12,10 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::payload::ProduceConsumePayload;
-use crate::proto::action::Action_Command;
+use crate::handler_v2::apply_v2;
+use crate::item_state::{is_expiry_date, DrawOrder, ItemState, Lot};
+use crate::payload::{Command, ProduceConsumePayload};
+use crate::proto::receipt::{LotDraw, LotDraws};
+use crate::quantity::{format_decimal, rescale, MAX_SCALE};
 use crypto::digest::Digest;
 use crypto::sha2::Sha512;
+use protobuf::{Message, RepeatedField};
+use std::collections::{BTreeMap, BTreeSet};
 
 use sawtooth_sdk::messages::processor::TpProcessRequest;
 use sawtooth_sdk::processor::handler::ApplyError;
@@ -24,6 +29,13 @@ use sawtooth_sdk::processor::handler::TransactionHandler;
 
 pub const PRODUCE_CONSUME: &str = "produce-consume";
 pub const VERSION: &str = "1.0";
+pub const VERSION_2: &str = "2.0";
+
+/// Version of what this handler writes to the state for a transaction. It
+/// goes up whenever the same transaction would write other bytes, such as
+/// a new `ItemState` layout, as the chain only verifies the blocks
+/// committed with the current version.
+pub(crate) const STATE_FORMAT: u32 = 1;
 
 pub struct ProduceConsumeHandler {
     family_name: String,
@@ -35,7 +47,7 @@ impl ProduceConsumeHandler {
     pub fn new() -> ProduceConsumeHandler {
         ProduceConsumeHandler {
             family_name: PRODUCE_CONSUME.to_string(),
-            family_versions: vec![VERSION.to_string()],
+            family_versions: vec![VERSION.to_string(), VERSION_2.to_string()],
             namespaces: vec![get_produce_consume_prefix().to_string()],
         }
     }
@@ -59,66 +71,642 @@ impl TransactionHandler for ProduceConsumeHandler {
         request: &TpProcessRequest,
         context: &mut dyn TransactionContext,
     ) -> Result<(), ApplyError> {
-        let payload = match ProduceConsumePayload::from(&request.get_payload()) {
-            Ok(decoded) => decoded,
-            Err(err) => return Err(ApplyError::InvalidTransaction(err.to_string())),
-        };
+        // Each family version has its own payload encoding
+        match request.get_header().get_family_version() {
+            VERSION => apply_v1(request, context),
+            VERSION_2 => apply_v2(request, context),
+            version => Err(ApplyError::InvalidTransaction(format!(
+                "Unsupported family version {}",
+                version
+            ))),
+        }
+    }
+}
+
+fn apply_v1(
+    request: &TpProcessRequest,
+    context: &mut dyn TransactionContext,
+) -> Result<(), ApplyError> {
+    let payload = match ProduceConsumePayload::from(&request.get_payload()) {
+        Ok(decoded) => decoded,
+        Err(err) => return Err(ApplyError::InvalidTransaction(err.to_string())),
+    };
+
+    // Owned items are kept under the signer's public key, so that a
+    // signer can only ever change its own stock
+    let owner = if payload.is_owner_scoped() {
+        Some(request.get_header().get_signer_public_key())
+    } else {
+        None
+    };
+
+    // Every item touched by the command with the change in its quantity,
+    // the operations of a MULTI command are applied in order
+    let updates = match payload.get_command() {
+        Command::Multi => {
+            let mut updates = vec![];
+            for operation in payload.get_operations() {
+                updates.extend(item_updates(operation)?);
+            }
+            updates
+        }
+        _ => item_updates(&payload)?,
+    };
+
+    // Version 1.0 predates the registry, its items are registered when
+    // they are first touched
+    apply_updates(context, owner, updates, payload.is_delete_empty(), false)
+}
+
+/// Change made to a single item by an operation. The quantities are in
+/// units of 10^-scale, the scale of the payload rather than the item's.
+#[derive(Debug)]
+pub(crate) enum ItemUpdate {
+    /// Adds a lot of the quantity with its expiry date
+    Produce(i64, u32, Option<String>),
+    /// Draws the quantity from the lots in the order
+    Consume(i64, u32, DrawOrder),
+    /// Adds the lots drawn by the previous update, the destination of a
+    /// transfer receives the lots of the source with their expiry dates
+    Receive,
+    /// Moves the quantity from available to a new reservation
+    Reserve(String, i64, u32),
+    /// Returns the quantity of the reservation to available
+    Release(String),
+    /// Consumes the quantity of the reservation
+    Commit(String),
+    /// Registers the item with its unit, description, capacity and scale
+    Create(String, String, Option<i64>, u32),
+}
 
+/// Applies the updates to the items. The new values are kept aside until
+/// every update succeeds, an item updated twice sees the result of its
+/// first update. With `registered_only` only a registration can add an
+/// item to the state, otherwise an unknown item is registered in whole
+/// units on its first update. With `delete_empty` the items consumed down
+/// to nothing are deleted.
+pub(crate) fn apply_updates(
+    context: &mut dyn TransactionContext,
+    owner: Option<&str>,
+    updates: Vec<(String, ItemUpdate)>,
+    delete_empty: bool,
+    registered_only: bool,
+) -> Result<(), ApplyError> {
+    let mut items: BTreeMap<String, ItemState> = BTreeMap::new();
+    let mut consumed = BTreeSet::new();
+    let mut created = BTreeSet::new();
+    // Lots drawn by the operations, the last draw in the scale of its item
+    let mut draws = vec![];
+    let mut last_draw = (vec![], 0);
+    for (identifier, update) in updates {
         // Compute address for the item
-        let address = compute_address(&payload.get_identifier());
+        let address = compute_address(owner, &identifier);
+        match update {
+            ItemUpdate::Consume(..) | ItemUpdate::Commit(_) => {
+                consumed.insert(address.clone());
+            }
+            _ => (),
+        }
 
         // Get the quantity in the store
-        let raw_value: Option<Vec<u8>> = match context.get_state_entry(&address) {
-            Ok(present) => present,
-            Err(err) => return Err(ApplyError::InternalError(err.to_string())),
+        let existing = match items.remove(&address) {
+            Some(item) => Some(item),
+            None => read_item(context, &address, &identifier)?,
         };
-        // Deserialize the value
-        let value = match raw_value {
-            Some(present) => {
-                let mut array: [u8; 4] = [0; 4];
-                array.copy_from_slice(&present[..4]);
-                i32::from_ne_bytes(array)
+        // Outside the registry an unknown item starts empty, in whole units
+        let existing = match (existing, &update) {
+            (None, ItemUpdate::Create(..)) => None,
+            (None, _) if !registered_only => {
+                info!("Registering the item {} on its first update", &identifier);
+                created.insert(address.clone());
+                Some(ItemState::new(&identifier, "", "", None, 0))
             }
-            None => 0,
+            (existing, _) => existing,
         };
-        info!("Read the value {}: {}", &payload.get_identifier(), value);
 
-        // Check for overflow scenarios
-        let new_value = match payload.get_command() {
-            Action_Command::PRODUCE => value.checked_add(payload.get_quantity()),
-            Action_Command::CONSUME => value.checked_sub(payload.get_quantity()),
+        let mut item = match (existing, update) {
+            (None, ItemUpdate::Create(unit, description, capacity, scale)) => {
+                if capacity.is_some_and(|capacity| capacity <= 0) {
+                    return Err(ApplyError::InvalidTransaction(
+                        "The capacity must be positive".to_string(),
+                    ));
+                }
+                if scale > MAX_SCALE {
+                    return Err(ApplyError::InvalidTransaction(format!(
+                        "An item has at most {} decimal places",
+                        MAX_SCALE
+                    )));
+                }
+                info!("Registering the item {}", &identifier);
+                created.insert(address.clone());
+                ItemState::new(&identifier, &unit, &description, capacity, scale)
+            }
+            (Some(_), ItemUpdate::Create(..)) => {
+                return Err(ApplyError::InvalidTransaction(format!(
+                    "Item {} already exists",
+                    identifier
+                )))
+            }
+            (None, _) => {
+                return Err(ApplyError::InvalidTransaction(format!(
+                    "Unknown item {}, it must be created first",
+                    identifier
+                )))
+            }
+            (Some(mut item), update) => {
+                info!("Read the value {}: {}", &identifier, item.get_quantity());
+                let drawn = update_item(&mut item, update, &last_draw)?;
+                for lot in &drawn {
+                    draws.push(lot_draw(&item, lot));
+                }
+                last_draw = (drawn, item.get_scale());
+                item
+            }
         };
-        // unwrapping is safe after none condition check
-        if new_value.is_none() || new_value.unwrap() < 0 {
-            return Err(ApplyError::InvalidTransaction(
-                "Invalid resultant quantity".to_string(),
-            ));
-        }
         info!(
             "Computed new value {}: {}",
-            &payload.get_identifier(),
-            new_value.unwrap()
+            &identifier,
+            item.get_quantity()
         );
 
-        // Either produce or consume successful, store the new state back, serialize the value
-        let new_value_bytes = new_value.unwrap().to_ne_bytes();
+        // The owner is kept with the item, its address alone does not
+        // tell an owned item from a shared one
+        item.set_owner(owner.unwrap_or_default());
+        items.insert(address, item);
+    }
 
-        context.set_state_entries(vec![(address, new_value_bytes.to_vec())])?;
+    // Serialize the values along with the identifiers
+    let mut entries = vec![];
+    let mut deleted = vec![];
+    for (address, item) in items {
+        // Only the entries already in the state can be deleted
+        if delete_empty
+            && consumed.contains(&address)
+            && !created.contains(&address)
+            && item.get_quantity() == 0
+            && item.get_reserved() == 0
+        {
+            info!("Deleting the empty item {}", item.get_identifier());
+            deleted.push(address);
+            continue;
+        }
+        match item.to_bytes() {
+            Ok(bytes) => entries.push((address, bytes)),
+            Err(err) => return Err(ApplyError::InternalError(err.to_string())),
+        }
+    }
+
+    // The lots drawn are reported in the receipt of the transaction
+    if !draws.is_empty() {
+        let mut lot_draws = LotDraws::new();
+        lot_draws.set_draws(RepeatedField::from_vec(draws));
+        match lot_draws.write_to_bytes() {
+            Ok(bytes) => context.add_receipt_data(&bytes)?,
+            Err(err) => return Err(ApplyError::InternalError(err.to_string())),
+        }
+    }
+
+    // All the updates are successful, store the new state back at once
+    if !entries.is_empty() {
+        context.set_state_entries(entries)?;
+    }
+    if !deleted.is_empty() {
+        context.delete_state_entries(&deleted)?;
+    }
+
+    Ok(())
+}
+
+/// Applies one update to the existing item, returning the lots it drew
+/// from. Neither the available quantity nor the total reserved may go
+/// negative or overflow, and together they stay within the capacity of the
+/// item. The lots received are the ones drawn by the previous update, in
+/// the scale of their item.
+fn update_item(
+    item: &mut ItemState,
+    update: ItemUpdate,
+    received: &(Vec<Lot>, u32),
+) -> Result<Vec<Lot>, ApplyError> {
+    let value = item.get_quantity();
+    let mut drawn = vec![];
+    match update {
+        ItemUpdate::Produce(quantity, scale, expiry) => {
+            let quantity = item_scale(item, quantity, scale)?;
+            item.set_quantity(checked_quantity(value.checked_add(quantity))?);
+            if quantity > 0 {
+                item.add_lot(quantity, expiry);
+            }
+        }
+        ItemUpdate::Consume(quantity, scale, order) => {
+            let quantity = item_scale(item, quantity, scale)?;
+            item.set_quantity(checked_quantity(value.checked_sub(quantity))?);
+            drawn = draw_lots(item, quantity, order)?;
+        }
+        ItemUpdate::Receive => {
+            let (lots, scale) = received;
+            let mut total = value;
+            for lot in lots {
+                let quantity = item_scale(item, lot.get_quantity(), *scale)?;
+                total = checked_quantity(total.checked_add(quantity))?;
+                item.add_lot(quantity, lot.get_expiry());
+            }
+            item.set_quantity(total);
+        }
+        ItemUpdate::Reserve(reservation, quantity, scale) => {
+            if item.get_reservation(&reservation).is_some() {
+                return Err(ApplyError::InvalidTransaction(format!(
+                    "Reservation {} already exists",
+                    reservation
+                )));
+            }
+            let quantity = item_scale(item, quantity, scale)?;
+            let available = checked_quantity(value.checked_sub(quantity))?;
+            checked_quantity(item.get_reserved().checked_add(quantity))?;
+            item.set_quantity(available);
+            item.set_reservation(&reservation, quantity);
+        }
+        ItemUpdate::Release(reservation) => {
+            let quantity = take_reservation(item, &reservation)?;
+            item.set_quantity(checked_quantity(value.checked_add(quantity))?);
+        }
+        ItemUpdate::Commit(reservation) => {
+            let quantity = take_reservation(item, &reservation)?;
+            drawn = draw_lots(item, quantity, DrawOrder::Fifo)?;
+        }
+        ItemUpdate::Create(..) => {
+            return Err(ApplyError::InternalError(
+                "Registration of an existing item".to_string(),
+            ))
+        }
+    }
+    let stock = checked_quantity(item.get_quantity().checked_add(item.get_reserved()))?;
+    match item.get_capacity() {
+        Some(capacity) if stock > capacity => Err(ApplyError::InvalidTransaction(format!(
+            "Capacity {} of {} exceeded",
+            format_decimal(capacity, item.get_scale()),
+            item.get_identifier()
+        ))),
+        _ => Ok(drawn),
+    }
+}
+
+fn draw_lots(
+    item: &mut ItemState,
+    quantity: i64,
+    order: DrawOrder,
+) -> Result<Vec<Lot>, ApplyError> {
+    match item.draw_lots(quantity, order) {
+        Some(drawn) => Ok(drawn),
+        None => Err(ApplyError::InvalidTransaction(format!(
+            "The lots of {} do not hold {}",
+            item.get_identifier(),
+            format_decimal(quantity, item.get_scale())
+        ))),
+    }
+}
+
+fn lot_draw(item: &ItemState, lot: &Lot) -> LotDraw {
+    let mut lot_draw = LotDraw::new();
+    lot_draw.set_identifier(item.get_identifier());
+    lot_draw.set_lot(lot.get_id());
+    lot_draw.set_quantity(lot.get_quantity());
+    lot_draw.set_scale(item.get_scale());
+    lot_draw.set_expiry(lot.get_expiry().unwrap_or_default());
+    lot_draw
+}
+
+/// Converts a quantity of the payload to the scale of the item, a quantity
+/// with more decimal places than the item has is rejected.
+fn item_scale(item: &ItemState, quantity: i64, scale: u32) -> Result<i64, ApplyError> {
+    match rescale(quantity, scale, item.get_scale()) {
+        Some(quantity) => Ok(quantity),
+        None => Err(ApplyError::InvalidTransaction(format!(
+            "{} has {} decimal places",
+            item.get_identifier(),
+            item.get_scale()
+        ))),
+    }
+}
 
-        Ok(())
+fn checked_quantity(value: Option<i64>) -> Result<i64, ApplyError> {
+    match value {
+        Some(quantity) if quantity >= 0 => Ok(quantity),
+        _ => Err(ApplyError::InvalidTransaction(
+            "Invalid resultant quantity".to_string(),
+        )),
     }
 }
 
-fn compute_address(identifier: &str) -> String {
-    let prefix = get_produce_consume_prefix();
+fn take_reservation(item: &mut ItemState, reservation: &str) -> Result<i64, ApplyError> {
+    match item.remove_reservation(reservation) {
+        Some(quantity) => Ok(quantity),
+        None => Err(ApplyError::InvalidTransaction(format!(
+            "Unknown reservation {} of {}",
+            reservation,
+            item.get_identifier()
+        ))),
+    }
+}
+
+/// Items touched by a single operation with their update, a transfer
+/// consumes the source and the destination receives the lots drawn.
+pub(crate) fn item_updates(
+    payload: &ProduceConsumePayload,
+) -> Result<Vec<(String, ItemUpdate)>, ApplyError> {
+    let quantity = payload.get_quantity();
+    let scale = payload.get_scale();
+    if quantity < 0 {
+        return Err(ApplyError::InvalidTransaction(
+            "The quantity cannot be negative".to_string(),
+        ));
+    }
+    match payload.get_command() {
+        Command::Produce => {
+            let expiry = payload.get_expiry();
+            if expiry
+                .as_deref()
+                .is_some_and(|expiry| !is_expiry_date(expiry))
+            {
+                return Err(ApplyError::InvalidTransaction(
+                    "The expiry date is written as YYYY-MM-DD".to_string(),
+                ));
+            }
+            Ok(vec![(
+                payload.get_identifier(),
+                ItemUpdate::Produce(quantity, scale, expiry),
+            )])
+        }
+        Command::Consume => Ok(vec![(
+            payload.get_identifier(),
+            ItemUpdate::Consume(quantity, scale, payload.get_draw_order()),
+        )]),
+        Command::Transfer => {
+            if payload.get_identifier() == payload.get_destination() {
+                return Err(ApplyError::InvalidTransaction(
+                    "Cannot transfer an item to itself".to_string(),
+                ));
+            }
+            Ok(vec![
+                (
+                    payload.get_identifier(),
+                    ItemUpdate::Consume(quantity, scale, DrawOrder::Fifo),
+                ),
+                (payload.get_destination(), ItemUpdate::Receive),
+            ])
+        }
+        Command::Reserve => Ok(vec![(
+            payload.get_identifier(),
+            ItemUpdate::Reserve(payload.get_reservation(), quantity, scale),
+        )]),
+        Command::Release => Ok(vec![(
+            payload.get_identifier(),
+            ItemUpdate::Release(payload.get_reservation()),
+        )]),
+        Command::Commit => Ok(vec![(
+            payload.get_identifier(),
+            ItemUpdate::Commit(payload.get_reservation()),
+        )]),
+        Command::CreateItem => Ok(vec![(
+            payload.get_identifier(),
+            ItemUpdate::Create(
+                payload.get_unit(),
+                payload.get_description(),
+                payload.get_capacity(),
+                scale,
+            ),
+        )]),
+        Command::Multi => Err(ApplyError::InvalidTransaction(
+            "Operations cannot be nested".to_string(),
+        )),
+    }
+}
+
+/// Reads the item stored at the address, if it is in the state.
+fn read_item(
+    context: &mut dyn TransactionContext,
+    address: &str,
+    identifier: &str,
+) -> Result<Option<ItemState>, ApplyError> {
+    let raw_value: Option<Vec<u8>> = match context.get_state_entry(address) {
+        Ok(present) => present,
+        Err(err) => return Err(ApplyError::InternalError(err.to_string())),
+    };
+    // Deserialize the value
+    let mut item = match raw_value {
+        Some(present) => match ItemState::from_bytes(&present) {
+            Ok(item) => item,
+            Err(err) => return Err(ApplyError::InvalidTransaction(err.to_string())),
+        },
+        None => return Ok(None),
+    };
+    // Entries written before the identifier was stored get it now
+    if item.get_identifier().is_empty() {
+        item.set_identifier(identifier);
+    }
+    Ok(Some(item))
+}
+
+/// Address of the item, owned items are kept under a prefix derived from
+/// the owner's public key.
+pub(crate) fn compute_address(owner: Option<&str>, identifier: &str) -> String {
     let mut sha = Sha512::new();
     sha.input_str(identifier);
-    let remaining = sha.result_str()[..64].to_string();
-    prefix + &remaining
+    match owner {
+        Some(owner) => {
+            let remaining = sha.result_str()[..48].to_string();
+            get_owner_prefix(owner) + &remaining
+        }
+        None => {
+            let remaining = sha.result_str()[..64].to_string();
+            get_produce_consume_prefix() + &remaining
+        }
+    }
 }
 
-fn get_produce_consume_prefix() -> String {
+/// Prefix of the addresses of every item owned by the public key
+pub(crate) fn get_owner_prefix(owner: &str) -> String {
+    let mut sha = Sha512::new();
+    sha.input_str(owner);
+    get_produce_consume_prefix() + &sha.result_str()[..16]
+}
+
+pub(crate) fn get_produce_consume_prefix() -> String {
     let mut sha = Sha512::new();
     sha.input_str(PRODUCE_CONSUME);
     sha.result_str()[..6].to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::encode_payload;
+    use sawtooth_sdk::processor::handler::ContextError;
+    use std::cell::RefCell;
+
+    /// Transaction context over an in-memory state
+    #[derive(Default)]
+    struct MemoryContext {
+        state: RefCell<BTreeMap<String, Vec<u8>>>,
+    }
+
+    impl TransactionContext for MemoryContext {
+        fn get_state_entries(
+            &self,
+            addresses: &[String],
+        ) -> Result<Vec<(String, Vec<u8>)>, ContextError> {
+            let state = self.state.borrow();
+            Ok(addresses
+                .iter()
+                .filter_map(|address| {
+                    state
+                        .get(address)
+                        .map(|value| (address.clone(), value.clone()))
+                })
+                .collect())
+        }
+
+        fn set_state_entries(&self, entries: Vec<(String, Vec<u8>)>) -> Result<(), ContextError> {
+            self.state.borrow_mut().extend(entries);
+            Ok(())
+        }
+
+        fn delete_state_entries(&self, addresses: &[String]) -> Result<Vec<String>, ContextError> {
+            let mut state = self.state.borrow_mut();
+            Ok(addresses
+                .iter()
+                .filter(|address| state.remove(*address).is_some())
+                .cloned()
+                .collect())
+        }
+
+        fn add_receipt_data(&self, _data: &[u8]) -> Result<(), ContextError> {
+            Ok(())
+        }
+
+        fn add_event(
+            &self,
+            _event_type: String,
+            _attributes: Vec<(String, String)>,
+            _data: &[u8],
+        ) -> Result<(), ContextError> {
+            Ok(())
+        }
+    }
+
+    fn apply(context: &mut MemoryContext, update: ItemUpdate) -> Result<(), ApplyError> {
+        apply_updates(
+            context,
+            None,
+            vec![("apple".to_string(), update)],
+            true,
+            true,
+        )
+    }
+
+    /// Applies the command line through the handler, as a transaction of
+    /// the family version
+    fn apply_line(
+        context: &mut MemoryContext,
+        family_version: &str,
+        line: &str,
+    ) -> Result<(), ApplyError> {
+        let payload = ProduceConsumePayload::new(line).unwrap();
+        let (payload, _, _) = encode_payload(&payload, None, family_version, false).unwrap();
+        let mut request = TpProcessRequest::new();
+        request
+            .mut_header()
+            .set_family_version(family_version.to_string());
+        request.set_payload(payload);
+        ProduceConsumeHandler::new().apply(&request, context)
+    }
+
+    /// Available and reserved quantities of the shared item
+    fn quantities(context: &MemoryContext, identifier: &str) -> (i64, i64) {
+        let address = compute_address(None, identifier);
+        let item = ItemState::from_bytes(&context.state.borrow()[&address]).unwrap();
+        (item.get_quantity(), item.get_reserved())
+    }
+
+    #[test]
+    fn a_failed_operation_rolls_back_the_whole_multi_command() {
+        let mut context = MemoryContext::default();
+        apply_line(&mut context, VERSION_2, "CREATE_ITEM apple kg").unwrap();
+        apply_line(&mut context, VERSION_2, "CREATE_ITEM pear kg").unwrap();
+        apply_line(&mut context, VERSION_2, "PRODUCE apple 5").unwrap();
+        let before = context.state.borrow().clone();
+
+        // The last operation over-consumes, the ones before it are undone
+        assert!(apply_line(
+            &mut context,
+            VERSION_2,
+            "PRODUCE apple 3; PRODUCE pear 2; CONSUME apple 10"
+        )
+        .is_err());
+        assert_eq!(*context.state.borrow(), before);
+
+        // The operations see each other's results when they all succeed
+        apply_line(
+            &mut context,
+            VERSION_2,
+            "PRODUCE apple 3; TRANSFER apple pear 6; CONSUME pear 1",
+        )
+        .unwrap();
+        assert_eq!(quantities(&context, "apple"), (2, 0));
+        assert_eq!(quantities(&context, "pear"), (5, 0));
+    }
+
+    #[test]
+    fn the_registry_rejects_unknown_and_duplicate_items() {
+        let mut context = MemoryContext::default();
+        assert!(apply_line(&mut context, VERSION_2, "PRODUCE apple 5").is_err());
+        assert!(context.state.borrow().is_empty());
+
+        apply_line(&mut context, VERSION_2, "CREATE_ITEM apple kg").unwrap();
+        apply_line(&mut context, VERSION_2, "PRODUCE apple 5").unwrap();
+        assert!(apply_line(&mut context, VERSION_2, "CREATE_ITEM apple g").is_err());
+        // A typo in the destination does not create a new item
+        assert!(apply_line(&mut context, VERSION_2, "TRANSFER apple aple 2").is_err());
+        assert_eq!(context.state.borrow().len(), 1);
+        assert_eq!(quantities(&context, "apple"), (5, 0));
+    }
+
+    #[test]
+    fn reservations_hold_stock_until_released_or_committed() {
+        let mut context = MemoryContext::default();
+        apply_line(&mut context, VERSION_2, "CREATE_ITEM apple kg").unwrap();
+        apply_line(&mut context, VERSION_2, "PRODUCE apple 5").unwrap();
+
+        apply_line(&mut context, VERSION_2, "RESERVE apple order_1 3").unwrap();
+        assert_eq!(quantities(&context, "apple"), (2, 3));
+        // The reserved stock is not available to consume or reserve again
+        assert!(apply_line(&mut context, VERSION_2, "CONSUME apple 3").is_err());
+        assert!(apply_line(&mut context, VERSION_2, "RESERVE apple order_2 3").is_err());
+        assert!(apply_line(&mut context, VERSION_2, "RESERVE apple order_1 1").is_err());
+
+        apply_line(&mut context, VERSION_2, "RELEASE apple order_1").unwrap();
+        assert_eq!(quantities(&context, "apple"), (5, 0));
+        assert!(apply_line(&mut context, VERSION_2, "COMMIT apple order_1").is_err());
+
+        apply_line(&mut context, VERSION_2, "RESERVE apple order_2 4").unwrap();
+        apply_line(&mut context, VERSION_2, "COMMIT apple order_2").unwrap();
+        assert_eq!(quantities(&context, "apple"), (1, 0));
+    }
+
+    #[test]
+    fn an_item_consumed_to_zero_is_deleted_with_its_registration() {
+        let mut context = MemoryContext::default();
+        let create = || ItemUpdate::Create("kg".to_string(), String::new(), None, 0);
+        apply(&mut context, create()).unwrap();
+        apply(&mut context, ItemUpdate::Produce(2, 0, None)).unwrap();
+        apply(&mut context, ItemUpdate::Consume(2, 0, DrawOrder::Fifo)).unwrap();
+        assert!(context.state.borrow().is_empty());
+
+        // The item is unknown again until it is created anew
+        assert!(apply(&mut context, ItemUpdate::Produce(1, 0, None)).is_err());
+        apply(&mut context, create()).unwrap();
+        apply(&mut context, ItemUpdate::Produce(1, 0, None)).unwrap();
+        let address = compute_address(None, "apple");
+        let item = ItemState::from_bytes(&context.state.borrow()[&address]).unwrap();
+        assert_eq!(item.get_quantity(), 1);
+    }
+}
@@ -0,0 +1,46 @@
+// Copyright 2019 Arun S M
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::handler::{apply_updates, item_updates};
+use crate::payload::ProduceConsumePayload;
+
+use sawtooth_sdk::messages::processor::TpProcessRequest;
+use sawtooth_sdk::processor::handler::ApplyError;
+use sawtooth_sdk::processor::handler::TransactionContext;
+
+/// Applies a version 2.0 transaction. Its payload is an `ActionV2` list of
+/// operations.
+pub(crate) fn apply_v2(
+    request: &TpProcessRequest,
+    context: &mut dyn TransactionContext,
+) -> Result<(), ApplyError> {
+    let payload = match ProduceConsumePayload::from_v2(request.get_payload()) {
+        Ok(decoded) => decoded,
+        Err(err) => return Err(ApplyError::InvalidTransaction(err.to_string())),
+    };
+
+    // Owned items are kept under the signer's public key
+    let owner = if payload.is_owner_scoped() {
+        Some(request.get_header().get_signer_public_key())
+    } else {
+        None
+    };
+
+    let mut updates = vec![];
+    for operation in payload.get_operations() {
+        updates.extend(item_updates(operation)?);
+    }
+
+    apply_updates(context, owner, updates, payload.is_delete_empty(), true)
+}
@@ -0,0 +1,82 @@
+// Copyright 2019 Arun S M
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::payload::ProduceConsumePayload;
+use crate::pc_error::PCError;
+use crate::quantity::parse_decimal;
+use regex::Regex;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// A row of the inventory file, with its line number for reporting.
+pub(crate) type InventoryRow = (usize, Result<ProduceConsumePayload, PCError>);
+
+/// Reads an inventory CSV file with `identifier,quantity` rows into payloads
+/// registering and producing the items. A header row and blank lines are
+/// skipped, every other row that cannot be read, or that repeats an
+/// identifier, is returned as an error for that row.
+pub(crate) fn read_inventory(path: &Path) -> Result<Vec<InventoryRow>, PCError> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) => return Err(PCError::from(err.to_string())),
+    };
+    let identifier_re = match Regex::new(r#"^[[:word:]]+$"#) {
+        Ok(match_expression) => match_expression,
+        Err(err) => return Err(PCError::from(err.to_string())),
+    };
+
+    let mut rows = vec![];
+    let mut identifiers = HashSet::new();
+    for (index, line) in content.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || (line_number == 1 && line.starts_with("identifier")) {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let row = match fields.as_slice() {
+            [identifier, quantity] => {
+                if !identifier_re.is_match(identifier) {
+                    Err(PCError::from(format!(
+                        "Invalid identifier {:?}",
+                        identifier
+                    )))
+                } else if !identifiers.insert(identifier.to_string()) {
+                    // The batch would register the item twice
+                    Err(PCError::from(format!(
+                        "Duplicate identifier {:?}",
+                        identifier
+                    )))
+                } else {
+                    // The item gets the decimal places of its quantity
+                    match parse_decimal(quantity.trim_start_matches('-')) {
+                        Ok(_) if quantity.starts_with('-') => {
+                            Err(PCError::from(format!("Negative quantity {}", quantity)))
+                        }
+                        Ok((value, scale)) => Ok(ProduceConsumePayload::multi(vec![
+                            ProduceConsumePayload::create_item(identifier, "", "", None, scale),
+                            ProduceConsumePayload::produce(identifier, value, scale),
+                        ])),
+                        Err(err) => Err(err),
+                    }
+                }
+            }
+            _ => Err(PCError::from("Expected \"<identifier>,<quantity>\"")),
+        };
+        rows.push((line_number, row));
+    }
+    Ok(rows)
+}
@@ -15,12 +15,14 @@
 use crate::pc_error::PCError;
 use std::io;
 
-pub(crate) fn usr_input() -> Result<String, PCError> {
+/// Reads a command from the user, none once the input has ended.
+pub(crate) fn usr_input() -> Result<Option<String>, PCError> {
     let mut line = String::new();
     println!("Enter your command: ");
     match io::stdin().read_line(&mut line) {
+        Ok(0) => return Ok(None),
         Ok(_) => info!("Read the line!"),
         Err(err) => return Err(PCError::from(err.to_string())),
     };
-    Ok(line.clone())
+    Ok(Some(line))
 }
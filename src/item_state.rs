@@ -0,0 +1,435 @@
+// Copyright 2019 Arun S M
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::pc_error::PCError;
+use crate::proto::item_state::{ItemState as ItemStateMessage, Lot as LotMessage, Reservation};
+use protobuf::{Message, RepeatedField};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// Prefix of the `ItemState` messages in the state. A legacy value is the
+/// quantity, which is never negative, so it cannot be the bytes of -1.
+const ITEM_STATE_MARKER: [u8; 4] = [0xff; 4];
+
+/// Layout of the `ItemState` message known to this reader. A new layout
+/// also moves the handler to a new `STATE_FORMAT`.
+const ITEM_STATE_VERSION: u32 = 1;
+
+/// Lot of the stock of a legacy value, which has no lots
+const UNTRACKED_LOT: u64 = 0;
+
+/// Length of a legacy value
+const LEGACY_QUANTITY_SIZE: usize = 4;
+
+/// Order the lots of an item are drawn in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum DrawOrder {
+    /// The oldest lot first
+    Fifo,
+    /// The lot expiring first, the lots without expiry last
+    Fefo,
+}
+
+/// Checks the expiry date is a calendar date written as YYYY-MM-DD, so
+/// that the dates sort in the order they expire.
+pub(crate) fn is_expiry_date(expiry: &str) -> bool {
+    let shaped = expiry.len() == 10
+        && expiry.char_indices().all(|(index, c)| match index {
+            4 | 7 => c == '-',
+            _ => c.is_ascii_digit(),
+        });
+    if !shaped {
+        return false;
+    }
+    // The date is ASCII, the digits can be sliced
+    let number = |start: usize, end: usize| u32::from_str(&expiry[start..end]).unwrap_or(0);
+    let (year, month, day) = (number(0, 4), number(5, 7), number(8, 10));
+    let leap_year = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap_year => 29,
+        2 => 28,
+        _ => 0,
+    };
+    day >= 1 && day <= days
+}
+
+/// A quantity of an item produced at once, with its expiry date.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Lot {
+    id: u64,
+    quantity: i64,
+    expiry: Option<String>,
+}
+
+impl Lot {
+    pub(crate) fn get_id(&self) -> u64 {
+        self.id
+    }
+
+    pub(crate) fn get_quantity(&self) -> i64 {
+        self.quantity
+    }
+
+    /// Expiry date as YYYY-MM-DD
+    pub(crate) fn get_expiry(&self) -> Option<String> {
+        self.expiry.clone()
+    }
+}
+
+/// The value stored at an item's address. The quantity is the available
+/// stock, the reserved quantities are kept apart under their reservation
+/// ids. The lots split the whole stock, available and reserved.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ItemState {
+    identifier: String,
+    quantity: i64,
+    reservations: BTreeMap<String, i64>,
+    unit: String,
+    description: String,
+    capacity: Option<i64>,
+    scale: u32,
+    lots: Vec<Lot>,
+    next_lot: u64,
+    owner: String,
+}
+
+impl ItemState {
+    /// A newly registered item, it has no stock yet. The quantities of the
+    /// item are in units of 10^-scale.
+    pub(crate) fn new(
+        identifier: &str,
+        unit: &str,
+        description: &str,
+        capacity: Option<i64>,
+        scale: u32,
+    ) -> ItemState {
+        ItemState {
+            identifier: identifier.to_string(),
+            quantity: 0,
+            reservations: BTreeMap::new(),
+            unit: unit.to_string(),
+            description: description.to_string(),
+            capacity,
+            scale,
+            lots: vec![],
+            next_lot: UNTRACKED_LOT + 1,
+            owner: String::new(),
+        }
+    }
+
+    /// Reads an `ItemState` message, or a legacy value written before the
+    /// message existed.
+    pub(crate) fn from_bytes(present: &[u8]) -> Result<ItemState, PCError> {
+        if !present.starts_with(&ITEM_STATE_MARKER) {
+            return ItemState::from_legacy_bytes(present);
+        }
+
+        let message = match ItemStateMessage::parse_from_bytes(&present[ITEM_STATE_MARKER.len()..])
+        {
+            Ok(message) => message,
+            Err(err) => return Err(PCError::from(err.to_string())),
+        };
+        if message.get_version() > ITEM_STATE_VERSION {
+            return Err(PCError::from(format!(
+                "Unsupported item state version {}",
+                message.get_version()
+            )));
+        }
+        Ok(ItemState {
+            identifier: message.get_identifier().to_string(),
+            quantity: message.get_quantity(),
+            reservations: message
+                .get_reservations()
+                .iter()
+                .map(|reservation| (reservation.get_id().to_string(), reservation.get_quantity()))
+                .collect(),
+            unit: message.get_unit().to_string(),
+            description: message.get_description().to_string(),
+            capacity: match message.get_capacity() {
+                0 => None,
+                capacity => Some(capacity),
+            },
+            scale: message.get_scale(),
+            lots: message
+                .get_lots()
+                .iter()
+                .map(|lot| Lot {
+                    id: lot.get_id(),
+                    quantity: lot.get_quantity(),
+                    expiry: match lot.get_expiry() {
+                        "" => None,
+                        expiry => Some(expiry.to_string()),
+                    },
+                })
+                .collect(),
+            next_lot: message.get_next_lot(),
+            owner: message.get_owner().to_string(),
+        })
+    }
+
+    /// Legacy values are the quantity as an i32 in the byte order of the
+    /// machine that wrote them, their identifier is left empty. Their stock
+    /// is kept in a single lot without expiry. They are never written any
+    /// more.
+    fn from_legacy_bytes(present: &[u8]) -> Result<ItemState, PCError> {
+        if present.len() != LEGACY_QUANTITY_SIZE {
+            return Err(PCError::from(format!(
+                "Invalid item state of {} bytes",
+                present.len()
+            )));
+        }
+        let mut array: [u8; LEGACY_QUANTITY_SIZE] = [0; LEGACY_QUANTITY_SIZE];
+        array.copy_from_slice(present);
+        let quantity = i64::from(i32::from_ne_bytes(array));
+        let lots = if quantity > 0 {
+            vec![Lot {
+                id: UNTRACKED_LOT,
+                quantity,
+                expiry: None,
+            }]
+        } else {
+            vec![]
+        };
+        Ok(ItemState {
+            identifier: String::new(),
+            quantity,
+            reservations: BTreeMap::new(),
+            unit: String::new(),
+            description: String::new(),
+            capacity: None,
+            scale: 0,
+            lots,
+            next_lot: UNTRACKED_LOT + 1,
+            owner: String::new(),
+        })
+    }
+
+    /// Writes the item as an `ItemState` message, a legacy item is
+    /// converted the first time it is updated.
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>, PCError> {
+        let mut message = ItemStateMessage::new();
+        message.set_version(ITEM_STATE_VERSION);
+        message.set_identifier(self.identifier.clone());
+        message.set_quantity(self.quantity);
+        message.set_reservations(RepeatedField::from_vec(
+            self.reservations
+                .iter()
+                .map(|(id, quantity)| {
+                    let mut reservation = Reservation::new();
+                    reservation.set_id(id.clone());
+                    reservation.set_quantity(*quantity);
+                    reservation
+                })
+                .collect(),
+        ));
+        message.set_unit(self.unit.clone());
+        message.set_description(self.description.clone());
+        message.set_capacity(self.capacity.unwrap_or(0));
+        message.set_scale(self.scale);
+        message.set_lots(RepeatedField::from_vec(
+            self.lots
+                .iter()
+                .map(|lot| {
+                    let mut lot_message = LotMessage::new();
+                    lot_message.set_id(lot.id);
+                    lot_message.set_quantity(lot.quantity);
+                    lot_message.set_expiry(lot.expiry.clone().unwrap_or_default());
+                    lot_message
+                })
+                .collect(),
+        ));
+        message.set_next_lot(self.next_lot);
+        message.set_owner(self.owner.clone());
+        let message_bytes = match message.write_to_bytes() {
+            Ok(bytes) => bytes,
+            Err(err) => return Err(PCError::from(err.to_string())),
+        };
+        let mut bytes = ITEM_STATE_MARKER.to_vec();
+        bytes.extend(message_bytes);
+        Ok(bytes)
+    }
+
+    pub(crate) fn get_identifier(&self) -> String {
+        self.identifier.clone()
+    }
+
+    pub(crate) fn get_quantity(&self) -> i64 {
+        self.quantity
+    }
+
+    pub(crate) fn get_scale(&self) -> u32 {
+        self.scale
+    }
+
+    pub(crate) fn get_unit(&self) -> String {
+        self.unit.clone()
+    }
+
+    pub(crate) fn get_description(&self) -> String {
+        self.description.clone()
+    }
+
+    pub(crate) fn get_capacity(&self) -> Option<i64> {
+        self.capacity
+    }
+
+    /// Public key of the signer owning the item, empty for a shared item
+    pub(crate) fn get_owner(&self) -> String {
+        self.owner.clone()
+    }
+
+    /// Total quantity held by the reservations of the item, the handler
+    /// keeps it within an i64
+    pub(crate) fn get_reserved(&self) -> i64 {
+        self.reservations.values().sum()
+    }
+
+    pub(crate) fn get_reservation(&self, reservation: &str) -> Option<i64> {
+        self.reservations.get(reservation).copied()
+    }
+
+    pub(crate) fn set_reservation(&mut self, reservation: &str, quantity: i64) {
+        self.reservations.insert(reservation.to_string(), quantity);
+    }
+
+    pub(crate) fn remove_reservation(&mut self, reservation: &str) -> Option<i64> {
+        self.reservations.remove(reservation)
+    }
+
+    /// Adds a lot of the quantity to the stock, returning its id. The
+    /// available quantity is updated separately.
+    pub(crate) fn add_lot(&mut self, quantity: i64, expiry: Option<String>) -> u64 {
+        let id = self.next_lot;
+        self.next_lot += 1;
+        self.lots.push(Lot {
+            id,
+            quantity,
+            expiry,
+        });
+        id
+    }
+
+    /// Takes the quantity out of the lots in the order, returning the part
+    /// drawn from each lot. Lots without expiry expire last. Nothing is
+    /// drawn if the lots do not hold the quantity.
+    pub(crate) fn draw_lots(&mut self, quantity: i64, order: DrawOrder) -> Option<Vec<Lot>> {
+        let mut indexes: Vec<usize> = (0..self.lots.len()).collect();
+        if order == DrawOrder::Fefo {
+            // The sort is stable, lots expiring together stay in FIFO order
+            indexes.sort_by_key(|index| {
+                let expiry = &self.lots[*index].expiry;
+                (expiry.is_none(), expiry.clone())
+            });
+        }
+
+        let mut drawn = vec![];
+        let mut remaining = quantity;
+        for index in indexes {
+            if remaining == 0 {
+                break;
+            }
+            let lot = &self.lots[index];
+            let taken = remaining.min(lot.quantity);
+            remaining -= taken;
+            drawn.push((index, taken));
+        }
+        if remaining > 0 {
+            return None;
+        }
+
+        let drawn = drawn
+            .into_iter()
+            .map(|(index, taken)| {
+                let lot = &mut self.lots[index];
+                lot.quantity -= taken;
+                Lot {
+                    id: lot.id,
+                    quantity: taken,
+                    expiry: lot.expiry.clone(),
+                }
+            })
+            .collect();
+        self.lots.retain(|lot| lot.quantity > 0);
+        Some(drawn)
+    }
+
+    pub(crate) fn set_identifier(&mut self, identifier: &str) {
+        self.identifier = identifier.to_string();
+    }
+
+    pub(crate) fn set_quantity(&mut self, quantity: i64) {
+        self.quantity = quantity;
+    }
+
+    pub(crate) fn set_owner(&mut self, owner: &str) {
+        self.owner = owner.to_string();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_value_is_exactly_the_quantity() {
+        for size in 0..LEGACY_QUANTITY_SIZE {
+            assert!(ItemState::from_bytes(&vec![1; size]).is_err());
+        }
+        let mut bytes = 7i32.to_ne_bytes().to_vec();
+        let item = ItemState::from_bytes(&bytes).unwrap();
+        assert_eq!(item.get_identifier(), "");
+        assert_eq!(item.get_quantity(), 7);
+        assert_eq!(item.lots.len(), 1);
+
+        bytes.extend(b"apple");
+        assert!(ItemState::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn fefo_draws_the_lots_without_expiry_last() {
+        let mut item = ItemState::new("apple", "kg", "", None, 0);
+        let first = item.add_lot(2, Some("2031-01-01".to_string()));
+        let undated = item.add_lot(5, None);
+        let earliest = item.add_lot(1, Some("2030-06-01".to_string()));
+        let tied = item.add_lot(3, Some("2031-01-01".to_string()));
+
+        let drawn = item.draw_lots(5, DrawOrder::Fefo).unwrap();
+        let drawn: Vec<(u64, i64)> = drawn
+            .iter()
+            .map(|lot| (lot.get_id(), lot.get_quantity()))
+            .collect();
+        // Lots expiring together are drawn in the order they were produced
+        assert_eq!(drawn, vec![(earliest, 1), (first, 2), (tied, 2)]);
+
+        let drawn = item.draw_lots(6, DrawOrder::Fefo).unwrap();
+        let drawn: Vec<(u64, i64)> = drawn
+            .iter()
+            .map(|lot| (lot.get_id(), lot.get_quantity()))
+            .collect();
+        assert_eq!(drawn, vec![(tied, 1), (undated, 5)]);
+        assert!(item.lots.is_empty());
+    }
+
+    #[test]
+    fn draw_lots_takes_nothing_beyond_the_stock() {
+        let mut item = ItemState::new("apple", "kg", "", None, 0);
+        item.add_lot(2, None);
+        item.add_lot(1, Some("2030-06-01".to_string()));
+        let lots = item.lots.clone();
+        assert_eq!(item.draw_lots(4, DrawOrder::Fefo), None);
+        assert_eq!(item.lots, lots);
+    }
+}
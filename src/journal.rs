@@ -0,0 +1,70 @@
+// Copyright 2019 Arun S M
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::file_store::{append_record, read_records};
+use crate::pc_error::PCError;
+use crate::proto::journal::JournalEntry;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const JOURNAL_FILE_NAME: &str = "journal";
+
+/// Ordered record of every state root committed by the application.
+/// It is kept in memory, and appended to a file in the data directory when
+/// there is one.
+pub(crate) struct Journal {
+    path: Option<PathBuf>,
+    entries: Vec<JournalEntry>,
+}
+
+impl Journal {
+    pub(crate) fn open(data_dir: Option<PathBuf>) -> Result<Journal, PCError> {
+        let path = data_dir.map(|dir| dir.join(JOURNAL_FILE_NAME));
+        let entries = match &path {
+            Some(file) => read_records::<JournalEntry>(file)?,
+            None => vec![],
+        };
+        Ok(Journal { path, entries })
+    }
+
+    /// Records a new commit on top of the latest entry.
+    pub(crate) fn append(&mut self, state_root: &str, batch_id: &str) -> Result<(), PCError> {
+        let timestamp = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(elapsed) => elapsed.as_secs(),
+            Err(err) => return Err(PCError::from(err.to_string())),
+        };
+        let mut entry = JournalEntry::new();
+        entry.set_height(self.entries.len() as u64);
+        entry.set_state_root(state_root.to_string());
+        entry.set_batch_id(batch_id.to_string());
+        entry.set_timestamp(timestamp);
+        if let Some(path) = &self.path {
+            append_record(path, &entry)?;
+        }
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    pub(crate) fn get_entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    pub(crate) fn get_entry(&self, height: u64) -> Option<&JournalEntry> {
+        self.entries.get(height as usize)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
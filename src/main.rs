@@ -17,26 +17,60 @@ extern crate regex;
 extern crate log;
 
 use crate::batcher::Batcher;
+use crate::chain::{verify_chain, Chain};
+use crate::command::UserCommand;
+use crate::config::{PCConfig, SchedulerMode};
+use crate::diff::{diff_states, ItemChange};
+use crate::export::export_items;
+use crate::file_store::{read_message, write_message, write_private_file};
+use crate::handler::{VERSION, VERSION_2};
+use crate::import::read_inventory;
+use crate::input::usr_input;
+use crate::item_state::ItemState;
+use crate::journal::Journal;
+use crate::pc_error::PCError;
 use crate::pc_state::PCState;
+use crate::proof::{generate_proof, verify_proof};
+use crate::proto::proof::MerkleProof;
+use crate::quantity::format_decimal;
+use crate::query::{list_items, query_quantity};
 use crate::scheduler::schedule;
-use crate::state_handler::commit_state;
-use crate::transaction::transaction_payload;
+use crate::state_handler::{commit_state, txn_reports, TxnReport};
+use crate::transaction::{encode_payload, transaction_payload};
 use cylinder::secp256k1::Secp256k1Context;
-use cylinder::{Context, Signer};
+use cylinder::{Context, PrivateKey, Signer};
 use log::LogLevelFilter;
 use log4rs::append::console::ConsoleAppender;
 use log4rs::config::{Appender, Config, Root};
 use log4rs::encode::pattern::PatternEncoder;
+use std::fs;
+use std::path::PathBuf;
 use std::process;
+use transact::protocol::batch::BatchPair;
+use transact::protocol::receipt::StateChange;
 use transact::state::merkle::{MerkleRadixTree, MerkleState};
 
 mod batcher;
+mod chain;
+mod command;
+mod config;
+mod diff;
+mod export;
+mod file_store;
 mod handler;
+mod handler_v2;
+mod import;
 mod input;
+mod item_state;
+mod journal;
+mod parallel_scheduler;
 mod payload;
 mod pc_error;
 mod pc_state;
+mod proof;
 mod proto;
+mod quantity;
+mod query;
 mod scheduler;
 mod state_handler;
 mod transaction;
@@ -49,15 +83,62 @@ mod transaction;
 /// This example accepts user input in the form of
 /// Command <item> <quantity>
 /// Where Command is either PRODUCE or CONSUME
-/// <item> is the identifier for the item
-/// <quantity> is a positive integer, the number of items
-/// produced/consumed.
+/// <item> is the identifier for the item, registered beforehand with
+/// CREATE_ITEM <item> <unit> [scale=<decimals>] [capacity] [description]
+/// <quantity> is a positive number, the number of items
+/// produced/consumed, with at most the decimal places of the item.
+/// Each PRODUCE adds a lot, which may end with its expiry date as
+/// YYYY-MM-DD. CONSUME draws from the oldest lots first, or from the lots
+/// expiring first if it ends with FEFO.
+/// A quantity is moved between two items atomically with
+/// TRANSFER <source> <destination> <quantity>
+/// Stock is held aside for an order, and then released or consumed, with
+/// RESERVE <identifier> <reservation> <quantity>
+/// RELEASE <identifier> <reservation>
+/// COMMIT <identifier> <reservation>
+/// Several of these separated by ';' are applied together in one
+/// transaction, either all of them succeed or none does.
+/// Transactions are submitted with the family version 2.0, unless
+/// `--family-version 1.0` is passed for the original payload.
+/// With `--delete-empty` the items consumed to zero are deleted from the
+/// state, their registration included.
+/// With `--scheduler parallel` the transactions of a batch that touch
+/// different addresses are executed concurrently.
+/// With `--owner-scoped` the items are owned by the signer, whose key is
+/// kept in the file passed with `--key-file`.
+///
+/// `HISTORY` prints the journal of committed state roots and
+/// `CHECKOUT <height>` makes the root at that height the working root.
+/// `VERIFY` re-walks the chain of blocks wrapping the committed batches.
+/// `QUERY <item>` prints the quantity of the item without a transaction.
+/// `LIST [page]` prints the items in the `produce-consume` namespace.
+/// `EXPORT <json|csv> <file> [state root]` writes the items to a file.
+/// `IMPORT <file>` produces the initial inventory from a CSV file.
+/// `BATCH <file>` submits the lines of a file as a single batch of
+/// transactions, committed together.
+/// The receipt of every transaction is printed after its batch is executed.
+/// `SUBMIT <file>` submits every line of a file as its own batch, all of
+/// them scheduled in a single run.
+/// `PROVE <item> <file> [state root]` writes a merkle proof of the item and
+/// `VERIFY_PROOF <file> [state root]` checks one against the state root.
+/// `DIFF <state root> [state root]` prints the items that changed between
+/// the two roots.
+///
+/// The state is kept in memory unless `--data-dir <directory>` is passed,
+/// in which case it is persisted in that directory and the application
+/// resumes from the last committed state root. The database is LMDB unless
+/// `--database sqlite` is passed.
 fn main() {
     init_logging();
 
+    let config = match PCConfig::from_args() {
+        Ok(config) => config,
+        Err(err) => panic!("Error: {:?}", err),
+    };
+
     // Initialize current state of the `produce-consume`
     // Start the executor
-    let cur_state = match PCState::new() {
+    let cur_state = match PCState::new(&config) {
         Ok(initialized) => initialized,
         Err(err) => panic!("Error: {:?}", err),
     };
@@ -69,45 +150,689 @@ fn main() {
         Ok(database) => database,
         Err(err) => panic!("Error: {:?}", err),
     };
-    let mut state_root = db.get_merkle_root();
+    // Resume from the last committed root, if there is one
+    let mut state_root = match cur_state.load_state_root() {
+        Ok(Some(stored_root)) => stored_root,
+        Ok(None) => db.get_merkle_root(),
+        Err(err) => panic!("Error: {:?}", err),
+    };
+    info!("Starting from the state root {}", state_root);
+
+    // Every commit is recorded in the journal, starting with the root
+    // the application began with
+    let mut journal = match Journal::open(cur_state.get_data_dir()) {
+        Ok(journal) => journal,
+        Err(err) => panic!("Error: {:?}", err),
+    };
+    if journal.is_empty() {
+        if let Err(err) = journal.append(&state_root, "") {
+            panic!("Error: {:?}", err);
+        }
+    }
 
-    // Generate a signer
-    let signer = new_signer();
+    // Every committed batch is wrapped in a block
+    let mut chain = match Chain::open(cur_state.get_data_dir()) {
+        Ok(chain) => chain,
+        Err(err) => panic!("Error: {:?}", err),
+    };
+
+    // Generate a signer, or load it from the key file
+    let signer = match load_signer(config.get_key_file()) {
+        Ok(signer) => signer,
+        Err(err) => panic!("Error: {:?}", err),
+    };
+    let public_key = match signer.public_key() {
+        Ok(public_key) => public_key.as_hex(),
+        Err(err) => panic!("Error: {:?}", err),
+    };
+    // Owned items are kept under the signer's public key
+    let owner = if config.is_owner_scoped() {
+        println!("Items are owned by {}", public_key);
+        Some(public_key)
+    } else {
+        None
+    };
 
     // Get the payload signed by the signer
-    let batcher_obj = Batcher::new(Box::from(signer));
+    let family_version = config.get_family_version();
+    let delete_empty = config.is_delete_empty();
+    let batcher_obj = Batcher::new(Box::from(signer), &family_version);
 
     loop {
+        // Get the command from the user
+        let line = match usr_input() {
+            Ok(Some(line)) => line,
+            // The input ended
+            Ok(None) => break,
+            Err(err) => panic!("Error: {:?}", err),
+        };
+        let command = match UserCommand::parse(&line) {
+            Ok(command) => command,
+            Err(err) => {
+                println!("{}", err);
+                continue;
+            }
+        };
+
+        let usr_input = match command {
+            UserCommand::Transaction(usr_input) => usr_input,
+            UserCommand::History => {
+                print_history(&journal);
+                continue;
+            }
+            UserCommand::Checkout(height) => {
+                state_root = match journal.get_entry(height) {
+                    Some(entry) => entry.get_state_root().to_string(),
+                    None => {
+                        println!("No state root at height {}", height);
+                        continue;
+                    }
+                };
+                if let Err(err) = cur_state.store_state_root(&state_root) {
+                    panic!("Failed {:?}", err);
+                }
+                println!("Switched to the state root {}", state_root);
+                continue;
+            }
+            UserCommand::Query {
+                identifier,
+                owner: item_owner,
+            } => {
+                let item_owner = item_owner.or_else(|| owner.clone());
+                match query_quantity(&statestore, &state_root, &identifier, item_owner.as_deref()) {
+                    Ok((quantity, scale)) => {
+                        println!("{}: {}", identifier, format_decimal(quantity, scale))
+                    }
+                    Err(err) => println!("Query failed: {}", err),
+                }
+                continue;
+            }
+            UserCommand::List {
+                page,
+                owner: item_owner,
+            } => {
+                let item_owner = item_owner.or_else(|| owner.clone());
+                match list_items(cur_state.get_db(), &state_root, page, item_owner.as_deref()) {
+                    Ok((items, has_more)) => {
+                        for (address, item) in items {
+                            println!(
+                                "{}: {} {}, {} reserved ({})",
+                                item.get_identifier(),
+                                display_quantity(&item),
+                                item.get_unit(),
+                                format_decimal(item.get_reserved(), item.get_scale()),
+                                address
+                            );
+                            if !item.get_description().is_empty() {
+                                println!("  {}", item.get_description());
+                            }
+                        }
+                        if has_more {
+                            println!("More items on page {}", page + 1);
+                        }
+                    }
+                    Err(err) => println!("List failed: {}", err),
+                }
+                continue;
+            }
+            UserCommand::Export {
+                format,
+                path,
+                state_root: export_root,
+            } => {
+                let export_root = export_root.unwrap_or_else(|| state_root.clone());
+                match export_items(cur_state.get_db(), &export_root, format, &path) {
+                    Ok(exported) => println!("Exported {} items to {:?}", exported, path),
+                    Err(err) => println!("Export failed: {}", err),
+                }
+                continue;
+            }
+            UserCommand::Import(path) => {
+                // Every row registers its item, which version 1.0 cannot
+                if family_version == VERSION {
+                    println!("Import needs the family version {}", VERSION_2);
+                    continue;
+                }
+                // The inventory is only imported on a fresh state
+                if state_root != journal.get_entries()[0].get_state_root() {
+                    println!("Import is only allowed on the genesis state root");
+                    continue;
+                }
+                let rows = match read_inventory(&path) {
+                    Ok(rows) => rows,
+                    Err(err) => {
+                        println!("Import failed: {}", err);
+                        continue;
+                    }
+                };
+                // Every row is a transaction of a single batch, the
+                // inventory is imported whole or not at all
+                let total = rows.len();
+                let mut labels = vec![];
+                let mut payloads = vec![];
+                for (line_number, row) in rows {
+                    match row.and_then(|pc_payload| {
+                        encode_payload(&pc_payload, owner.as_deref(), &family_version, delete_empty)
+                    }) {
+                        Ok(payload) => {
+                            labels.push(format!("Row {}", line_number));
+                            payloads.push(payload);
+                        }
+                        Err(err) => println!("Row {} failed: {}", line_number, err),
+                    }
+                }
+                if payloads.len() < total {
+                    println!("Import failed, none of the {} rows was imported", total);
+                    continue;
+                }
+                let batch = match batcher_obj.multi_txn(payloads) {
+                    Ok(batch) => batch,
+                    Err(err) => {
+                        println!("Import failed: {}", err);
+                        continue;
+                    }
+                };
+                let labels = transaction_labels(labels, &batch);
+                let (outcome, reports) = execute_batch(
+                    &cur_state,
+                    &statestore,
+                    &mut journal,
+                    &mut chain,
+                    &state_root,
+                    batch,
+                );
+                print_receipts(&labels, &reports);
+                match outcome {
+                    Ok(new_state_root) => {
+                        state_root = new_state_root;
+                        println!("Imported {} rows", total);
+                    }
+                    Err(err) => println!("Import failed: {}", err),
+                }
+                continue;
+            }
+            UserCommand::Batch(path) => {
+                // Every line of the file is a transaction of the batch
+                let lines = match fs::read_to_string(&path) {
+                    Ok(content) => content,
+                    Err(err) => {
+                        println!("Batch failed: {}", err);
+                        continue;
+                    }
+                };
+                let mut labels = vec![];
+                let mut payloads = vec![];
+                let mut failed = false;
+                for (index, line) in lines.lines().enumerate() {
+                    let line = line.trim();
+                    if line.is_empty() {
+                        continue;
+                    }
+                    match transaction_payload(
+                        line,
+                        owner.as_deref(),
+                        &family_version,
+                        delete_empty,
+                        &statestore,
+                        &state_root,
+                    ) {
+                        Ok(payload) => {
+                            labels.push(format!("Line {}", index + 1));
+                            payloads.push(payload);
+                        }
+                        Err(err) => {
+                            println!("Line {} failed: {}", index + 1, err);
+                            failed = true;
+                        }
+                    }
+                }
+                if failed {
+                    println!("Batch failed, no transaction was submitted");
+                    continue;
+                }
+                let transactions = payloads.len();
+                let batch = match batcher_obj.multi_txn(payloads) {
+                    Ok(batch) => batch,
+                    Err(err) => {
+                        println!("Batch failed: {}", err);
+                        continue;
+                    }
+                };
+                let labels = transaction_labels(labels, &batch);
+                let (outcome, reports) = execute_batch(
+                    &cur_state,
+                    &statestore,
+                    &mut journal,
+                    &mut chain,
+                    &state_root,
+                    batch,
+                );
+                print_receipts(&labels, &reports);
+                match outcome {
+                    Ok(new_state_root) => {
+                        state_root = new_state_root;
+                        println!("Committed a batch of {} transactions", transactions);
+                    }
+                    Err(err) => println!("Batch failed: {}", err),
+                }
+                continue;
+            }
+            UserCommand::Submit(path) => {
+                // Every line of the file is a batch, the lines that cannot
+                // be read are skipped
+                let lines = match fs::read_to_string(&path) {
+                    Ok(content) => content,
+                    Err(err) => {
+                        println!("Submit failed: {}", err);
+                        continue;
+                    }
+                };
+                let mut labels = vec![];
+                let mut batches = vec![];
+                for (index, line) in lines.lines().enumerate() {
+                    let line = line.trim();
+                    if line.is_empty() {
+                        continue;
+                    }
+                    match transaction_payload(
+                        line,
+                        owner.as_deref(),
+                        &family_version,
+                        delete_empty,
+                        &statestore,
+                        &state_root,
+                    )
+                    .and_then(|(usr_payload, inputs, outputs)| {
+                        batcher_obj.single_txn(&usr_payload, inputs, outputs)
+                    }) {
+                        Ok(batch) => {
+                            let label = format!("Line {}", index + 1);
+                            let txn_labels = transaction_labels(vec![label.clone()], &batch);
+                            labels.push((label, txn_labels));
+                            batches.push(batch);
+                        }
+                        Err(err) => println!("Line {} failed: {}", index + 1, err),
+                    }
+                }
+                if batches.is_empty() {
+                    println!("Submit failed, no line could be read");
+                    continue;
+                }
+                let outcomes = match execute_batches(
+                    &cur_state,
+                    &statestore,
+                    &mut journal,
+                    &mut chain,
+                    &state_root,
+                    batches,
+                ) {
+                    Ok(outcomes) => outcomes,
+                    Err(err) => {
+                        println!("Submit failed: {}", err);
+                        continue;
+                    }
+                };
+                let mut committed = 0;
+                for ((label, txn_labels), (outcome, reports)) in labels.into_iter().zip(outcomes) {
+                    print_receipts(&txn_labels, &reports);
+                    match outcome {
+                        Ok(new_state_root) => {
+                            state_root = new_state_root;
+                            committed += 1;
+                        }
+                        Err(err) => println!("{} failed: {}", label, err),
+                    }
+                }
+                println!("Committed {} batches", committed);
+                continue;
+            }
+            UserCommand::Prove {
+                identifier,
+                path,
+                state_root: proof_root,
+            } => {
+                let proof_root = proof_root.unwrap_or_else(|| state_root.clone());
+                match generate_proof(
+                    cur_state.get_db(),
+                    &proof_root,
+                    &identifier,
+                    owner.as_deref(),
+                )
+                .and_then(|proof| write_message(&path, &proof))
+                {
+                    Ok(_) => println!("Wrote the proof of {} to {:?}", identifier, path),
+                    Err(err) => println!("Proof failed: {}", err),
+                }
+                continue;
+            }
+            UserCommand::VerifyProof {
+                path,
+                state_root: trusted_root,
+            } => {
+                let trusted_root = trusted_root.unwrap_or_else(|| state_root.clone());
+                let proof = match read_message::<MerkleProof>(&path) {
+                    Ok(Some(proof)) => proof,
+                    Ok(None) => {
+                        println!("No proof at {:?}", path);
+                        continue;
+                    }
+                    Err(err) => {
+                        println!("Proof verification failed: {}", err);
+                        continue;
+                    }
+                };
+                match verify_proof(&proof, &trusted_root, owner.as_deref()) {
+                    Ok(item) => println!(
+                        "Proof verified, {}: {}",
+                        item.get_identifier(),
+                        display_quantity(&item)
+                    ),
+                    Err(err) => println!("Proof verification failed: {}", err),
+                }
+                continue;
+            }
+            UserCommand::Diff { from_root, to_root } => {
+                let to_root = to_root.unwrap_or_else(|| state_root.clone());
+                match diff_states(cur_state.get_db(), &from_root, &to_root) {
+                    Ok(changes) => print_changes(&changes),
+                    Err(err) => println!("Diff failed: {}", err),
+                }
+                continue;
+            }
+            UserCommand::Verify => {
+                // The genesis entry is always present in the journal
+                let genesis_root = journal.get_entries()[0].get_state_root();
+                match verify_chain(&chain, genesis_root, &cur_state, &statestore) {
+                    Ok(verified) => println!("Verified {} blocks", verified),
+                    Err(err) => println!("Verification failed: {}", err),
+                }
+                continue;
+            }
+        };
+
         // Get the payload from the user
-        let (usr_payload, inputs, outputs) = match transaction_payload() {
+        let (usr_payload, inputs, outputs) = match transaction_payload(
+            &usr_input,
+            owner.as_deref(),
+            &family_version,
+            delete_empty,
+            &statestore,
+            &state_root,
+        ) {
             Ok(valid) => valid,
-            Err(err) => panic!("Error: {:?}", err),
+            Err(err) => {
+                println!("Error: {}", err);
+                continue;
+            }
         };
 
         let batch = match batcher_obj.single_txn(&usr_payload, inputs, outputs) {
             Ok(batch) => batch,
-            Err(err) => panic!("Error: {:?}", err),
-        };
-
-        let result = match schedule(&cur_state, batch, &state_root) {
-            Ok(success) => success,
-            Err(err) => panic!("Failed {:?}", err),
+            Err(err) => {
+                println!("Error: {}", err);
+                continue;
+            }
         };
+        let labels = transaction_labels(vec!["Transaction".to_string()], &batch);
 
-        state_root = match commit_state(&statestore, &state_root, result) {
+        let (outcome, reports) = execute_batch(
+            &cur_state,
+            &statestore,
+            &mut journal,
+            &mut chain,
+            &state_root,
+            batch,
+        );
+        print_receipts(&labels, &reports);
+        match outcome {
             Ok(new_state_root) => {
                 println!("Done");
-                new_state_root
+                state_root = new_state_root;
             }
-            Err(err) => panic!("Failed {:?}", err),
+            Err(err) => println!("Failed: {}", err),
         };
     }
 }
 
-fn new_signer() -> Box<dyn Signer> {
+/// Result of committing a batch, the new state root when it is committed,
+/// with the reports of its transactions.
+type BatchOutcome = (Result<String, PCError>, Vec<TxnReport>);
+
+/// Schedules the batch on the state root and commits the result, unless it
+/// was committed before. The new state root is recorded in the journal,
+/// the chain and the data directory.
+fn execute_batch(
+    cur_state: &PCState,
+    statestore: &MerkleState,
+    journal: &mut Journal,
+    chain: &mut Chain,
+    state_root: &str,
+    batch: BatchPair,
+) -> BatchOutcome {
+    match execute_batches(
+        cur_state,
+        statestore,
+        journal,
+        chain,
+        state_root,
+        vec![batch],
+    ) {
+        Ok(mut outcomes) => match outcomes.pop() {
+            Some(outcome) => outcome,
+            None => (
+                Err(PCError::from("Unexpected error, no result found")),
+                vec![],
+            ),
+        },
+        Err(err) => (Err(err), vec![]),
+    }
+}
+
+/// Schedules the batches in a single run on the state root and commits
+/// their results in order, each on the root committed before it. Returns
+/// the outcome of every batch, the new state root of a committed one. An
+/// invalid batch is not committed and the batches after it are applied
+/// without it. Any other failure stops the commits, the batches after it
+/// are not committed. The new state roots are recorded in the journal, the
+/// chain and the data directory.
+fn execute_batches(
+    cur_state: &PCState,
+    statestore: &MerkleState,
+    journal: &mut Journal,
+    chain: &mut Chain,
+    state_root: &str,
+    batches: Vec<BatchPair>,
+) -> Result<Vec<BatchOutcome>, PCError> {
+    // A batch or transaction replayed is rejected before it is scheduled
+    for batch in &batches {
+        chain.check_replay(batch.batch())?;
+    }
+
+    let results = schedule(cur_state, batches, state_root)?;
+    let mut outcomes = vec![];
+    let mut parent_root = state_root.to_string();
+    let scheduled = results.len();
+    let mut results = results.into_iter();
+    while let Some(result) = results.next() {
+        let committed_batch = result.batch.batch().clone();
+        let reports = txn_reports(&result);
+        if let Some(error_message) = batch_error(&reports, committed_batch.header_signature()) {
+            outcomes.push((Err(PCError::from(error_message)), reports));
+            // The serial scheduler ran the next batches on the changes of
+            // the invalid one, they are executed again without it
+            if cur_state.get_scheduler_mode() == SchedulerMode::Serial {
+                let next_batches: Vec<BatchPair> = results.map(|result| result.batch).collect();
+                if !next_batches.is_empty() {
+                    match execute_batches(
+                        cur_state,
+                        statestore,
+                        journal,
+                        chain,
+                        &parent_root,
+                        next_batches,
+                    ) {
+                        Ok(next_outcomes) => outcomes.extend(next_outcomes),
+                        Err(err) => outcomes.push((Err(err), vec![])),
+                    }
+                }
+                break;
+            }
+            continue;
+        }
+        let outcome = commit_state(statestore, &parent_root, result).and_then(|new_state_root| {
+            journal.append(&new_state_root, committed_batch.header_signature())?;
+            chain.append(committed_batch, &parent_root, &new_state_root)?;
+            cur_state.store_state_root(&new_state_root)?;
+            Ok(new_state_root)
+        });
+        match outcome {
+            Ok(new_state_root) => {
+                parent_root = new_state_root.clone();
+                outcomes.push((Ok(new_state_root), reports));
+            }
+            Err(err) => {
+                // The next batches were executed on top of this one, they
+                // cannot be committed without it
+                outcomes.push((Err(err), reports));
+                break;
+            }
+        }
+    }
+    while outcomes.len() < scheduled {
+        outcomes.push((
+            Err(PCError::from("Not committed, an earlier batch failed")),
+            vec![],
+        ));
+    }
+    Ok(outcomes)
+}
+
+/// Reason a batch is invalid, taken from the transaction that failed. The
+/// other transactions of an invalid batch are invalid because of it.
+fn batch_error(reports: &[TxnReport], batch_id: &str) -> Option<String> {
+    let containing = format!("containing batch ({}) is invalid", batch_id);
+    let mut errors = reports.iter().filter_map(TxnReport::get_error_message);
+    let first = errors.clone().next();
+    errors
+        .find(|error_message| *error_message != containing)
+        .or(first)
+        .map(str::to_string)
+}
+
+/// Pairs the labels of the inputs with the header signatures of the
+/// transactions built from them, in the order they were added to the batch.
+fn transaction_labels(labels: Vec<String>, batch: &BatchPair) -> Vec<(String, String)> {
+    labels
+        .into_iter()
+        .zip(
+            batch
+                .batch()
+                .transactions()
+                .iter()
+                .map(|transaction| transaction.header_signature().to_string()),
+        )
+        .collect()
+}
+
+/// Prints the receipt of every transaction of a batch, under the label of
+/// the input it was built from. The labels are matched by transaction id.
+fn print_receipts(labels: &[(String, String)], reports: &[TxnReport]) {
+    for report in reports {
+        let label = labels
+            .iter()
+            .find(|(_, transaction_id)| transaction_id == report.get_transaction_id())
+            .map_or("Transaction", |(label, _)| label.as_str());
+        if report.is_valid() {
+            println!(
+                "{}: valid, transaction {}",
+                label,
+                report.get_transaction_id()
+            );
+        } else {
+            println!(
+                "{}: invalid, transaction {}: {}",
+                label,
+                report.get_transaction_id(),
+                report.get_error_message().unwrap_or_default()
+            );
+        }
+        for state_change in report.get_state_changes() {
+            match state_change {
+                StateChange::Set { key, .. } => println!("  set {}", key),
+                StateChange::Delete { key } => println!("  delete {}", key),
+            }
+        }
+        for draw in report.get_lot_draws() {
+            println!(
+                "  drew {} of {} from lot {}",
+                format_decimal(draw.get_quantity(), draw.get_scale()),
+                draw.get_identifier(),
+                draw.get_lot()
+            );
+        }
+    }
+}
+
+fn print_history(journal: &Journal) {
+    for entry in journal.get_entries() {
+        println!(
+            "{:>6} {} {} {}",
+            entry.get_height(),
+            entry.get_timestamp(),
+            entry.get_state_root(),
+            entry.get_batch_id()
+        );
+    }
+}
+
+fn print_changes(changes: &[ItemChange]) {
+    for change in changes {
+        match change {
+            ItemChange::Added(new) => {
+                println!("+ {}: {}", new.get_identifier(), display_quantity(new))
+            }
+            ItemChange::Removed(old) => {
+                println!("- {}: {}", old.get_identifier(), display_quantity(old))
+            }
+            ItemChange::Changed { old, new } => println!(
+                "~ {}: {} -> {}",
+                new.get_identifier(),
+                display_quantity(old),
+                display_quantity(new)
+            ),
+        }
+    }
+    println!("{} items changed", changes.len());
+}
+
+fn display_quantity(item: &ItemState) -> String {
+    format_decimal(item.get_quantity(), item.get_scale())
+}
+
+/// Loads the signer's private key from the key file, the file is created
+/// with a new random key the first time. Without a key file a random key is
+/// used for the run.
+fn load_signer(key_file: Option<PathBuf>) -> Result<Box<dyn Signer>, PCError> {
     let context = Secp256k1Context::new();
-    let key = context.new_random_private_key();
-    context.new_signer(key)
+    let key = match key_file {
+        Some(path) if path.exists() => {
+            let key_hex = match fs::read_to_string(&path) {
+                Ok(key_hex) => key_hex,
+                Err(err) => return Err(PCError::from(err.to_string())),
+            };
+            match PrivateKey::new_from_hex(key_hex.trim()) {
+                Ok(key) => key,
+                Err(err) => return Err(PCError::from(err.to_string())),
+            }
+        }
+        Some(path) => {
+            let key = context.new_random_private_key();
+            write_private_file(&path, key.as_hex().as_bytes())?;
+            key
+        }
+        None => context.new_random_private_key(),
+    };
+    Ok(context.new_signer(key))
 }
 
 fn init_logging() {
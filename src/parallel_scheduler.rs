@@ -0,0 +1,501 @@
+// Copyright 2019 Arun S M
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use transact::context::{ContextId, ContextLifecycle};
+use transact::protocol::batch::BatchPair;
+use transact::protocol::receipt::{TransactionReceipt, TransactionResult};
+use transact::protocol::transaction::TransactionPair;
+use transact::scheduler::{
+    BatchExecutionResult, ExecutionTask, ExecutionTaskCompletionNotification,
+    ExecutionTaskCompletionNotifier, InvalidTransactionResult, Scheduler, SchedulerError,
+};
+
+type ResultCallback = Box<dyn Fn(Option<BatchExecutionResult>) + Send>;
+type ErrorCallback = Box<dyn Fn(SchedulerError) + Send>;
+
+/// Scheduler running the transactions whose declared addresses do not
+/// conflict at the same time. A transaction waits for the transactions
+/// before it, in its batch or in the valid batches before, that write an
+/// address it reads or writes, or read an address it writes. It reads the
+/// state through their contexts. The batches are executed one after the
+/// other, the results are the ones of the serial scheduler.
+///
+/// Unlike the serial scheduler it hands out several task iterators, one
+/// for each executor taking its tasks.
+pub(crate) struct ParallelScheduler {
+    shared: Arc<(Mutex<Shared>, Condvar)>,
+    task_iterators: usize,
+}
+
+impl ParallelScheduler {
+    pub(crate) fn new(
+        context_lifecycle: Box<dyn ContextLifecycle>,
+        state_id: String,
+        task_iterators: usize,
+    ) -> ParallelScheduler {
+        let shared = Shared {
+            context_lifecycle,
+            state_id,
+            result_callback: Box::new(|_| warn!("No result callback set")),
+            error_callback: Box::new(|err| error!("No error callback set: {}", err)),
+            batches: VecDeque::new(),
+            current: None,
+            applied: vec![],
+            finalized: false,
+            done: false,
+        };
+        ParallelScheduler {
+            shared: Arc::new((Mutex::new(shared), Condvar::new())),
+            task_iterators,
+        }
+    }
+}
+
+impl Scheduler for ParallelScheduler {
+    fn set_result_callback(&mut self, callback: ResultCallback) -> Result<(), SchedulerError> {
+        lock(&self.shared)?.result_callback = callback;
+        Ok(())
+    }
+
+    fn set_error_callback(&mut self, callback: ErrorCallback) -> Result<(), SchedulerError> {
+        lock(&self.shared)?.error_callback = callback;
+        Ok(())
+    }
+
+    fn add_batch(&mut self, batch: BatchPair) -> Result<(), SchedulerError> {
+        let mut shared = lock(&self.shared)?;
+        if shared.finalized {
+            return Err(SchedulerError::SchedulerFinalized);
+        }
+        let current = shared.current.as_ref().map(|current| &current.batch);
+        if shared.batches.contains(&batch) || current == Some(&batch) {
+            return Err(SchedulerError::DuplicateBatch(
+                batch.batch().header_signature().to_string(),
+            ));
+        }
+        shared.batches.push_back(batch);
+        self.shared.1.notify_all();
+        Ok(())
+    }
+
+    fn cancel(&mut self) -> Result<Vec<BatchPair>, SchedulerError> {
+        let mut shared = lock(&self.shared)?;
+        let mut cancelled: Vec<BatchPair> = shared
+            .current
+            .take()
+            .map(|current| current.batch)
+            .into_iter()
+            .collect();
+        cancelled.extend(shared.batches.drain(..));
+        shared.try_shutdown();
+        self.shared.1.notify_all();
+        Ok(cancelled)
+    }
+
+    fn finalize(&mut self) -> Result<(), SchedulerError> {
+        let mut shared = lock(&self.shared)?;
+        shared.finalized = true;
+        shared.try_shutdown();
+        self.shared.1.notify_all();
+        Ok(())
+    }
+
+    fn take_task_iterator(
+        &mut self,
+    ) -> Result<Box<dyn Iterator<Item = ExecutionTask> + Send>, SchedulerError> {
+        if self.task_iterators == 0 {
+            return Err(SchedulerError::NoTaskIterator);
+        }
+        self.task_iterators -= 1;
+        Ok(Box::new(ParallelTaskIterator {
+            shared: Arc::clone(&self.shared),
+        }))
+    }
+
+    fn new_notifier(&mut self) -> Result<Box<dyn ExecutionTaskCompletionNotifier>, SchedulerError> {
+        Ok(Box::new(ParallelNotifier {
+            shared: Arc::clone(&self.shared),
+        }))
+    }
+}
+
+fn lock(shared: &(Mutex<Shared>, Condvar)) -> Result<MutexGuard<'_, Shared>, SchedulerError> {
+    match shared.0.lock() {
+        Ok(guard) => Ok(guard),
+        Err(err) => Err(SchedulerError::Internal(format!(
+            "The scheduler lock is poisoned: {}",
+            err
+        ))),
+    }
+}
+
+/// Hands out the transactions as they become ready, it ends once the
+/// scheduler is finalized and every batch is executed.
+struct ParallelTaskIterator {
+    shared: Arc<(Mutex<Shared>, Condvar)>,
+}
+
+impl Iterator for ParallelTaskIterator {
+    type Item = ExecutionTask;
+
+    fn next(&mut self) -> Option<ExecutionTask> {
+        let (mutex, ready) = &*self.shared;
+        let mut shared = match mutex.lock() {
+            Ok(shared) => shared,
+            Err(err) => {
+                error!("The scheduler lock is poisoned: {}", err);
+                return None;
+            }
+        };
+        loop {
+            if let Some(task) = shared.next_task() {
+                // Another iterator may take the next ready transaction
+                ready.notify_all();
+                return Some(task);
+            }
+            if shared.done {
+                return None;
+            }
+            shared = match ready.wait(shared) {
+                Ok(shared) => shared,
+                Err(err) => {
+                    error!("The scheduler lock is poisoned: {}", err);
+                    return None;
+                }
+            };
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ParallelNotifier {
+    shared: Arc<(Mutex<Shared>, Condvar)>,
+}
+
+impl ExecutionTaskCompletionNotifier for ParallelNotifier {
+    fn notify(&self, notification: ExecutionTaskCompletionNotification) {
+        let mut shared = match lock(&self.shared) {
+            Ok(shared) => shared,
+            Err(err) => {
+                error!("{}", err);
+                return;
+            }
+        };
+        shared.complete(notification);
+        self.shared.1.notify_all();
+    }
+
+    fn clone_box(&self) -> Box<dyn ExecutionTaskCompletionNotifier> {
+        Box::new(self.clone())
+    }
+}
+
+/// Addresses a transaction declares, the prefix of an address stands for
+/// all the addresses under it.
+struct Footprint {
+    inputs: Vec<Vec<u8>>,
+    outputs: Vec<Vec<u8>>,
+}
+
+impl Footprint {
+    fn of(pair: &TransactionPair) -> Footprint {
+        Footprint {
+            inputs: pair.header().inputs().to_vec(),
+            outputs: pair.header().outputs().to_vec(),
+        }
+    }
+
+    /// Whether a later transaction with the footprint has to see the
+    /// effects of this one, or must not be seen by it.
+    fn conflicts_with(&self, later: &Footprint) -> bool {
+        overlap(&self.outputs, &later.inputs)
+            || overlap(&self.outputs, &later.outputs)
+            || overlap(&self.inputs, &later.outputs)
+    }
+}
+
+fn overlap(addresses: &[Vec<u8>], others: &[Vec<u8>]) -> bool {
+    addresses.iter().any(|address| {
+        others
+            .iter()
+            .any(|other| address.starts_with(other) || other.starts_with(address))
+    })
+}
+
+enum TxnStatus {
+    Waiting(Box<TransactionPair>),
+    Running,
+    Valid(ContextId),
+    Invalid,
+}
+
+struct BatchTxn {
+    id: String,
+    footprint: Footprint,
+    status: TxnStatus,
+}
+
+struct CurrentBatch {
+    batch: BatchPair,
+    txns: Vec<BatchTxn>,
+    running: usize,
+    failure: Option<InvalidTransactionResult>,
+}
+
+/// A transaction of a valid batch, the later batches read its changes
+struct AppliedTxn {
+    context_id: ContextId,
+    footprint: Footprint,
+}
+
+struct Shared {
+    context_lifecycle: Box<dyn ContextLifecycle>,
+    state_id: String,
+    result_callback: ResultCallback,
+    error_callback: ErrorCallback,
+    batches: VecDeque<BatchPair>,
+    current: Option<CurrentBatch>,
+    applied: Vec<AppliedTxn>,
+    finalized: bool,
+    /// The end of the results was sent, the iterators return none
+    done: bool,
+}
+
+impl Shared {
+    /// Takes the next transaction ready to execute, starting the next
+    /// batch when the current one is done.
+    fn next_task(&mut self) -> Option<ExecutionTask> {
+        if self.current.is_none() && !self.start_batch() {
+            return None;
+        }
+        let current = self.current.as_mut()?;
+        if current.failure.is_some() {
+            return None;
+        }
+
+        let index = (0..current.txns.len()).find(|index| {
+            let txn = &current.txns[*index];
+            match txn.status {
+                TxnStatus::Waiting(_) => current.txns[..*index].iter().all(|earlier| match earlier
+                    .status
+                {
+                    TxnStatus::Valid(_) => true,
+                    _ => !earlier.footprint.conflicts_with(&txn.footprint),
+                }),
+                _ => false,
+            }
+        })?;
+
+        // The latest writers come first, the context manager reads a key
+        // from the first base context holding it
+        let txn = &current.txns[index];
+        let mut base_contexts = vec![];
+        for earlier in current.txns[..index].iter().rev() {
+            if let TxnStatus::Valid(context_id) = earlier.status {
+                if earlier.footprint.conflicts_with(&txn.footprint) {
+                    base_contexts.push(context_id);
+                }
+            }
+        }
+        for applied in self.applied.iter().rev() {
+            if applied.footprint.conflicts_with(&txn.footprint) {
+                base_contexts.push(applied.context_id);
+            }
+        }
+        let context_id = self
+            .context_lifecycle
+            .create_context(&base_contexts, &self.state_id);
+
+        let txn = &mut current.txns[index];
+        let pair = match std::mem::replace(&mut txn.status, TxnStatus::Running) {
+            TxnStatus::Waiting(pair) => *pair,
+            _ => return None,
+        };
+        current.running += 1;
+        Some(ExecutionTask::new(pair, context_id))
+    }
+
+    /// Makes the next batch the current one, a batch with an ill-formed
+    /// transaction is invalid without being executed.
+    fn start_batch(&mut self) -> bool {
+        while let Some(batch) = self.batches.pop_front() {
+            let mut txns = vec![];
+            let mut failure = None;
+            for transaction in batch.batch().transactions() {
+                let id = transaction.header_signature().to_string();
+                match transaction.clone().into_pair() {
+                    Ok(pair) => txns.push(BatchTxn {
+                        id,
+                        footprint: Footprint::of(&pair),
+                        status: TxnStatus::Waiting(Box::new(pair)),
+                    }),
+                    Err(err) => {
+                        failure = Some(InvalidTransactionResult {
+                            transaction_id: id,
+                            error_message: format!("ill-formed transaction: {}", err),
+                            error_data: vec![],
+                        });
+                        break;
+                    }
+                }
+            }
+            let executable = failure.is_none() && !txns.is_empty();
+            self.current = Some(CurrentBatch {
+                batch,
+                txns,
+                running: 0,
+                failure,
+            });
+            if executable {
+                return true;
+            }
+            self.finish_batch();
+        }
+        self.try_shutdown();
+        false
+    }
+
+    /// Records the outcome of an executed transaction, the batch result is
+    /// sent once none of its transactions is running.
+    fn complete(&mut self, notification: ExecutionTaskCompletionNotification) {
+        let (transaction_id, status, failure) = match notification {
+            ExecutionTaskCompletionNotification::Valid(context_id, transaction_id) => {
+                (transaction_id, TxnStatus::Valid(context_id), None)
+            }
+            ExecutionTaskCompletionNotification::Invalid(_, result) => (
+                result.transaction_id.clone(),
+                TxnStatus::Invalid,
+                Some(result),
+            ),
+        };
+        let current = match self.current.as_mut() {
+            Some(current) => current,
+            None => {
+                (self.error_callback)(SchedulerError::UnexpectedNotification(transaction_id));
+                return;
+            }
+        };
+        let txn = match current
+            .txns
+            .iter_mut()
+            .find(|txn| txn.id == transaction_id && matches!(txn.status, TxnStatus::Running))
+        {
+            Some(txn) => txn,
+            None => {
+                (self.error_callback)(SchedulerError::UnexpectedNotification(transaction_id));
+                return;
+            }
+        };
+        txn.status = status;
+        current.running -= 1;
+        if current.failure.is_none() {
+            current.failure = failure;
+        }
+
+        let executed = current
+            .txns
+            .iter()
+            .all(|txn| matches!(txn.status, TxnStatus::Valid(_)));
+        if current.running == 0 && (executed || current.failure.is_some()) {
+            self.finish_batch();
+            self.try_shutdown();
+        }
+    }
+
+    /// Sends the result of the current batch. The receipts of a valid batch
+    /// are read from the contexts, in the order of the transactions. In an
+    /// invalid batch the transactions other than the failed one are
+    /// invalid as part of the batch, as the serial scheduler reports them.
+    fn finish_batch(&mut self) {
+        let mut current = match self.current.take() {
+            Some(current) => current,
+            None => return,
+        };
+
+        let mut receipts = vec![];
+        if current.failure.is_none() {
+            for txn in &current.txns {
+                if let TxnStatus::Valid(context_id) = txn.status {
+                    match self
+                        .context_lifecycle
+                        .get_transaction_receipt(&context_id, &txn.id)
+                    {
+                        Ok(receipt) => receipts.push(receipt),
+                        Err(err) => {
+                            current.failure = Some(InvalidTransactionResult {
+                                transaction_id: txn.id.clone(),
+                                error_message: err.to_string(),
+                                error_data: vec![],
+                            });
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        let batch_id = current.batch.batch().header_signature().to_string();
+        match current.failure {
+            None => {
+                for txn in current.txns {
+                    if let TxnStatus::Valid(context_id) = txn.status {
+                        self.applied.push(AppliedTxn {
+                            context_id,
+                            footprint: txn.footprint,
+                        });
+                    }
+                }
+            }
+            Some(failure) => {
+                receipts = current
+                    .batch
+                    .batch()
+                    .transactions()
+                    .iter()
+                    .map(|transaction| {
+                        let transaction_id = transaction.header_signature();
+                        if transaction_id == failure.transaction_id {
+                            TransactionReceipt::from(failure.clone())
+                        } else {
+                            TransactionReceipt {
+                                transaction_id: transaction_id.to_string(),
+                                transaction_result: TransactionResult::Invalid {
+                                    error_message: format!(
+                                        "containing batch ({}) is invalid",
+                                        batch_id
+                                    ),
+                                    error_data: vec![],
+                                },
+                            }
+                        }
+                    })
+                    .collect();
+            }
+        }
+        (self.result_callback)(Some(BatchExecutionResult {
+            batch: current.batch,
+            receipts,
+        }));
+    }
+
+    /// Ends the results once the scheduler is finalized and idle
+    fn try_shutdown(&mut self) {
+        if self.finalized && !self.done && self.current.is_none() && self.batches.is_empty() {
+            self.done = true;
+            (self.result_callback)(None);
+        }
+    }
+}
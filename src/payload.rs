@@ -12,31 +12,77 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::item_state::{is_expiry_date, DrawOrder};
 use crate::pc_error::PCError;
 use crate::proto::action::Action;
 use crate::proto::action::Action_Command;
+use crate::proto::action_v2::{ActionV2, Operation_Command, Operation_DrawOrder};
+use crate::quantity::{parse_decimal, rescale, MAX_SCALE};
 use regex::Regex;
 use std::str::FromStr;
 use transact::handler::ApplyError;
 
+/// Operation of a payload. The version 1.0 `Action` schema is frozen, it
+/// only carries PRODUCE, CONSUME, TRANSFER and MULTI.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Command {
+    Produce,
+    Consume,
+    Transfer,
+    /// Several operations applied together
+    Multi,
+    Reserve,
+    Release,
+    Commit,
+    CreateItem,
+}
+
 #[derive(Debug)]
 pub(crate) struct ProduceConsumePayload {
-    command: Action_Command,
+    command: Command,
     identifier: String,
-    quantity: i32,
+    quantity: i64,
+    scale: u32,
+    destination: String,
+    reservation: String,
+    unit: String,
+    description: String,
+    capacity: i64,
+    expiry: String,
+    draw_order: DrawOrder,
+    owner_scoped: bool,
+    delete_empty: bool,
+    operations: Vec<ProduceConsumePayload>,
 }
 
 impl ProduceConsumePayload {
     pub(crate) fn new(text: &str) -> Result<ProduceConsumePayload, PCError> {
+        // Several operations separated by ';' are applied together
+        if !text.contains(';') {
+            return ProduceConsumePayload::new_operation(text.trim());
+        }
+        let mut operations = vec![];
+        for operation_text in text.split(';') {
+            operations.push(ProduceConsumePayload::new_operation(operation_text.trim())?);
+        }
+        Ok(ProduceConsumePayload::multi(operations))
+    }
+
+    fn new_operation(text: &str) -> Result<ProduceConsumePayload, PCError> {
+        if text.starts_with("CREATE_ITEM") {
+            return ProduceConsumePayload::new_create_item(text);
+        }
         // match the command line arguments against the valid pattern
         // and prepare a payload that can be serialized.
-        let re = match Regex::new(r#"^(PRODUCE|CONSUME)[ ][[:word:]]+[ ][[:word:]]+"#) {
+        let re = match Regex::new(
+            r#"^(((PRODUCE|CONSUME)[ ][[:word:]]+|(TRANSFER|RESERVE)[ ][[:word:]]+[ ][[:word:]]+)[ ][[:digit:]]+([.][[:digit:]]+)?|(RELEASE|COMMIT)[ ][[:word:]]+[ ][[:word:]]+)"#,
+        ) {
             Ok(match_expression) => match_expression,
             Err(err) => return Err(PCError::from(err.to_string())),
         };
         if !re.is_match(text) {
             return Err(PCError::from(
-                "Please input \"[PRODUCE|CONSUME] <identifier> <quantity>\"",
+                "Please input \"PRODUCE <identifier> <quantity> [YYYY-MM-DD]\", \"CONSUME <identifier> <quantity> [FIFO|FEFO]\", \"TRANSFER <source> <destination> <quantity>\", \"RESERVE <identifier> <reservation> <quantity>\" or \"[RELEASE|COMMIT] <identifier> <reservation>\"",
             ));
         }
 
@@ -44,23 +90,66 @@ impl ProduceConsumePayload {
         let words: Vec<&str> = text.split(' ').collect();
         // A pattern is matched, it's expected to have the word 0
         let action_command = match words.get(0).unwrap().clone() {
-            "PRODUCE" => Action_Command::PRODUCE,
-            "CONSUME" => Action_Command::CONSUME,
+            "PRODUCE" => Command::Produce,
+            "CONSUME" => Command::Consume,
+            "TRANSFER" => Command::Transfer,
+            "RESERVE" => Command::Reserve,
+            "RELEASE" => Command::Release,
+            "COMMIT" => Command::Commit,
             _ => panic!("Unexpected command action found"),
         };
 
         // A pattern is matched, there should be a identifier
         let identifier = words.get(1).unwrap().clone();
 
-        // A pattern is matches, there should be a quantity
-        let mut quantity_string = words.get(2).unwrap().to_string();
+        // A transfer has the destination before the quantity, the
+        // reservation commands have the reservation id
+        let (destination, reservation) = match action_command {
+            Command::Transfer => (words.get(2).unwrap().to_string(), String::new()),
+            Command::Reserve | Command::Release | Command::Commit => {
+                (String::new(), words.get(2).unwrap().to_string())
+            }
+            _ => (String::new(), String::new()),
+        };
 
-        info!("Quantity is {}", quantity_string);
-        quantity_string.truncate(quantity_string.len() - 1);
+        // RELEASE and COMMIT act on the whole reserved quantity
+        let (quantity, scale) = match action_command {
+            Command::Release | Command::Commit => (0, 0),
+            _ => {
+                let quantity_index = match action_command {
+                    Command::Transfer | Command::Reserve => 3,
+                    _ => 2,
+                };
+                // A pattern is matches, there should be a quantity
+                let quantity_string = words.get(quantity_index).unwrap();
 
-        let quantity = match i32::from_str(&quantity_string) {
-            Ok(value_read) => value_read,
-            Err(err) => return Err(PCError::from(err.to_string())),
+                info!("Quantity is {}", quantity_string);
+
+                parse_decimal(quantity_string)?
+            }
+        };
+
+        // A produced lot may expire, a consumption may pick the lots
+        // expiring first. Both follow the quantity.
+        let (expiry, draw_order) = match (action_command, words.get(3).cloned()) {
+            (Command::Produce, Some(expiry)) => {
+                if !is_expiry_date(expiry) {
+                    return Err(PCError::from(format!(
+                        "Invalid expiry date {}, please input YYYY-MM-DD",
+                        expiry
+                    )));
+                }
+                (expiry.to_string(), DrawOrder::Fifo)
+            }
+            (Command::Consume, Some("FIFO")) => (String::new(), DrawOrder::Fifo),
+            (Command::Consume, Some("FEFO")) => (String::new(), DrawOrder::Fefo),
+            (Command::Consume, Some(order)) => {
+                return Err(PCError::from(format!(
+                    "Invalid order {}, please input FIFO or FEFO",
+                    order
+                )))
+            }
+            _ => (String::new(), DrawOrder::Fifo),
         };
 
         // Create a payload structure with the information parsed
@@ -68,9 +157,146 @@ impl ProduceConsumePayload {
             command: action_command,
             identifier: identifier.to_string(),
             quantity,
+            scale,
+            destination,
+            reservation,
+            unit: String::new(),
+            description: String::new(),
+            capacity: 0,
+            expiry,
+            draw_order,
+            owner_scoped: false,
+            delete_empty: false,
+            operations: vec![],
         })
     }
 
+    /// Parses "CREATE_ITEM <identifier> <unit> [capacity] [description]",
+    /// the description is the rest of the line.
+    fn new_create_item(text: &str) -> Result<ProduceConsumePayload, PCError> {
+        let re = match Regex::new(r#"^CREATE_ITEM[ ][[:word:]]+[ ][[:word:]]+"#) {
+            Ok(match_expression) => match_expression,
+            Err(err) => return Err(PCError::from(err.to_string())),
+        };
+        if !re.is_match(text) {
+            return Err(PCError::from(
+                "Please input \"CREATE_ITEM <identifier> <unit> [scale=<decimals>] [capacity] [description]\"",
+            ));
+        }
+
+        let words: Vec<&str> = text.split(' ').collect();
+        let identifier = words.get(1).unwrap();
+        let unit = words.get(2).unwrap();
+        let mut rest = &words[3..];
+        // The decimal places of the item, whole units by default
+        let scale = match rest.first().and_then(|word| word.strip_prefix("scale=")) {
+            Some(scale) => {
+                rest = &rest[1..];
+                match u32::from_str(scale) {
+                    Ok(scale) if scale <= MAX_SCALE => scale,
+                    _ => {
+                        return Err(PCError::from(format!(
+                            "The scale is a number of decimal places up to {}",
+                            MAX_SCALE
+                        )))
+                    }
+                }
+            }
+            None => 0,
+        };
+        // The capacity is optional, a description can start with a word
+        let capacity = match rest.first().map(|word| parse_decimal(word)) {
+            Some(Ok((capacity, capacity_scale))) => {
+                rest = &rest[1..];
+                match rescale(capacity, capacity_scale, scale) {
+                    Some(capacity) if capacity > 0 => Some(capacity),
+                    Some(_) => return Err(PCError::from("The capacity must be positive")),
+                    None => {
+                        return Err(PCError::from(format!(
+                            "The capacity has more than {} decimal places",
+                            scale
+                        )))
+                    }
+                }
+            }
+            _ => None,
+        };
+        Ok(ProduceConsumePayload::create_item(
+            identifier,
+            unit,
+            &rest.join(" "),
+            capacity,
+            scale,
+        ))
+    }
+
+    /// Payload registering the item, its quantities are in units of
+    /// 10^-scale
+    pub(crate) fn create_item(
+        identifier: &str,
+        unit: &str,
+        description: &str,
+        capacity: Option<i64>,
+        scale: u32,
+    ) -> ProduceConsumePayload {
+        ProduceConsumePayload {
+            command: Command::CreateItem,
+            identifier: identifier.to_string(),
+            quantity: 0,
+            scale,
+            destination: String::new(),
+            reservation: String::new(),
+            unit: unit.to_string(),
+            description: description.to_string(),
+            capacity: capacity.unwrap_or(0),
+            expiry: String::new(),
+            draw_order: DrawOrder::Fifo,
+            owner_scoped: false,
+            delete_empty: false,
+            operations: vec![],
+        }
+    }
+
+    /// Payload applying the operations together
+    pub(crate) fn multi(operations: Vec<ProduceConsumePayload>) -> ProduceConsumePayload {
+        ProduceConsumePayload {
+            command: Command::Multi,
+            identifier: String::new(),
+            quantity: 0,
+            scale: 0,
+            destination: String::new(),
+            reservation: String::new(),
+            unit: String::new(),
+            description: String::new(),
+            capacity: 0,
+            expiry: String::new(),
+            draw_order: DrawOrder::Fifo,
+            owner_scoped: false,
+            delete_empty: false,
+            operations,
+        }
+    }
+
+    /// Payload producing a quantity of the item, in units of 10^-scale
+    pub(crate) fn produce(identifier: &str, quantity: i64, scale: u32) -> ProduceConsumePayload {
+        ProduceConsumePayload {
+            command: Command::Produce,
+            identifier: identifier.to_string(),
+            quantity,
+            scale,
+            destination: String::new(),
+            reservation: String::new(),
+            unit: String::new(),
+            description: String::new(),
+            capacity: 0,
+            expiry: String::new(),
+            draw_order: DrawOrder::Fifo,
+            owner_scoped: false,
+            delete_empty: false,
+            operations: vec![],
+        }
+    }
+
     /// Convert from bytes to
     pub(crate) fn from(raw_bytes: &[u8]) -> Result<ProduceConsumePayload, ApplyError> {
         info!("Payload in raw is {:?}", &raw_bytes);
@@ -78,14 +304,97 @@ impl ProduceConsumePayload {
             Ok(result) => result,
             Err(e) => return Err(e),
         };
+        Ok(ProduceConsumePayload::from_action(&parsed_payload))
+    }
+
+    /// Convert from the bytes of a version 2.0 `ActionV2` payload, its
+    /// operations are returned as a MULTI command
+    pub(crate) fn from_v2(raw_bytes: &[u8]) -> Result<ProduceConsumePayload, ApplyError> {
+        info!("Payload in raw is {:?}", &raw_bytes);
+        let parsed_payload: ActionV2 = match parse_from(&raw_bytes) {
+            Ok(result) => result,
+            Err(e) => return Err(e),
+        };
+        let operations = parsed_payload
+            .get_operations()
+            .iter()
+            .map(|operation| ProduceConsumePayload {
+                command: match operation.get_command() {
+                    Operation_Command::PRODUCE => Command::Produce,
+                    Operation_Command::CONSUME => Command::Consume,
+                    Operation_Command::TRANSFER => Command::Transfer,
+                    Operation_Command::RESERVE => Command::Reserve,
+                    Operation_Command::RELEASE => Command::Release,
+                    Operation_Command::COMMIT => Command::Commit,
+                    Operation_Command::CREATE_ITEM => Command::CreateItem,
+                },
+                identifier: operation.get_identifier().to_string(),
+                quantity: operation.get_quantity(),
+                scale: operation.get_scale(),
+                destination: operation.get_destination().to_string(),
+                reservation: operation.get_reservation().to_string(),
+                unit: operation.get_unit().to_string(),
+                description: operation.get_description().to_string(),
+                capacity: operation.get_capacity(),
+                expiry: operation.get_expiry().to_string(),
+                draw_order: match operation.get_draw_order() {
+                    Operation_DrawOrder::FIFO => DrawOrder::Fifo,
+                    Operation_DrawOrder::FEFO => DrawOrder::Fefo,
+                },
+                owner_scoped: parsed_payload.get_owner_scoped(),
+                delete_empty: parsed_payload.get_delete_empty(),
+                operations: vec![],
+            })
+            .collect();
         Ok(ProduceConsumePayload {
-            command: parsed_payload.get_command(),
-            identifier: parsed_payload.get_identifier().to_string(),
-            quantity: parsed_payload.get_quantity(),
+            command: Command::Multi,
+            identifier: String::new(),
+            quantity: 0,
+            scale: 0,
+            destination: String::new(),
+            reservation: String::new(),
+            unit: String::new(),
+            description: String::new(),
+            capacity: 0,
+            expiry: String::new(),
+            draw_order: DrawOrder::Fifo,
+            owner_scoped: parsed_payload.get_owner_scoped(),
+            delete_empty: parsed_payload.get_delete_empty(),
+            operations,
         })
     }
 
-    pub(crate) fn get_command(&self) -> Action_Command {
+    /// Version 1.0 quantities are whole units, its items have no lot
+    /// expiry and are never deleted
+    fn from_action(action: &Action) -> ProduceConsumePayload {
+        ProduceConsumePayload {
+            command: match action.get_command() {
+                Action_Command::PRODUCE => Command::Produce,
+                Action_Command::CONSUME => Command::Consume,
+                Action_Command::TRANSFER => Command::Transfer,
+                Action_Command::MULTI => Command::Multi,
+            },
+            identifier: action.get_identifier().to_string(),
+            quantity: i64::from(action.get_quantity()),
+            scale: 0,
+            destination: action.get_destination().to_string(),
+            reservation: String::new(),
+            unit: String::new(),
+            description: String::new(),
+            capacity: 0,
+            expiry: String::new(),
+            draw_order: DrawOrder::Fifo,
+            owner_scoped: action.get_owner_scoped(),
+            delete_empty: false,
+            operations: action
+                .get_operations()
+                .iter()
+                .map(ProduceConsumePayload::from_action)
+                .collect(),
+        }
+    }
+
+    pub(crate) fn get_command(&self) -> Command {
         return self.command;
     }
 
@@ -93,9 +402,64 @@ impl ProduceConsumePayload {
         return self.identifier.clone();
     }
 
-    pub(crate) fn get_quantity(&self) -> i32 {
+    pub(crate) fn get_quantity(&self) -> i64 {
         return self.quantity;
     }
+
+    /// The quantity and the capacity are in units of 10^-scale
+    pub(crate) fn get_scale(&self) -> u32 {
+        return self.scale;
+    }
+
+    pub(crate) fn get_destination(&self) -> String {
+        return self.destination.clone();
+    }
+
+    pub(crate) fn get_reservation(&self) -> String {
+        return self.reservation.clone();
+    }
+
+    pub(crate) fn get_unit(&self) -> String {
+        return self.unit.clone();
+    }
+
+    pub(crate) fn get_description(&self) -> String {
+        return self.description.clone();
+    }
+
+    /// Capacity of the registered item, if it has one
+    pub(crate) fn get_capacity(&self) -> Option<i64> {
+        match self.capacity {
+            0 => None,
+            capacity => Some(capacity),
+        }
+    }
+
+    /// Expiry date of the lot produced, if it has one
+    pub(crate) fn get_expiry(&self) -> Option<String> {
+        match self.expiry.as_str() {
+            "" => None,
+            expiry => Some(expiry.to_string()),
+        }
+    }
+
+    /// Order in which the lots are consumed
+    pub(crate) fn get_draw_order(&self) -> DrawOrder {
+        return self.draw_order;
+    }
+
+    pub(crate) fn is_owner_scoped(&self) -> bool {
+        return self.owner_scoped;
+    }
+
+    /// Whether the items consumed to zero are deleted
+    pub(crate) fn is_delete_empty(&self) -> bool {
+        return self.delete_empty;
+    }
+
+    pub(crate) fn get_operations(&self) -> &[ProduceConsumePayload] {
+        return &self.operations;
+    }
 }
 
 fn parse_from<T>(data: &[u8]) -> Result<T, ApplyError>
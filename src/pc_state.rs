@@ -12,29 +12,67 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::config::{DatabaseKind, PCConfig, SchedulerMode};
+use crate::file_store::write_file;
 use crate::handler::ProduceConsumeHandler;
 use crate::pc_error::PCError;
+use std::fs;
+use std::path::{Path, PathBuf};
 use transact::context::manager::sync::ContextManager;
 use transact::database::btree::BTreeDatabase;
+use transact::database::lmdb::{LmdbContext, LmdbDatabase};
+use transact::database::sqlite::SqliteDatabase;
 use transact::database::Database;
 use transact::execution::adapter::static_adapter::StaticExecutionAdapter;
 use transact::execution::executor::Executor;
 use transact::sawtooth::SawtoothToTransactHandlerAdapter;
 use transact::state::merkle::{MerkleState, INDEXES};
 
+const LMDB_FILE_NAME: &str = "merkle.lmdb";
+const SQLITE_FILE_NAME: &str = "merkle.sqlite";
+const STATE_ROOT_FILE_NAME: &str = "state_root";
+
+/// Executors started for the parallel scheduler
+const PARALLEL_EXECUTION_UNITS: usize = 4;
+
 pub(crate) struct PCState {
     db: Box<dyn Database>,
     context_manager: ContextManager,
-    executor: Executor,
+    executors: Vec<Executor>,
+    data_dir: Option<PathBuf>,
+    scheduler_mode: SchedulerMode,
 }
 
 impl PCState {
-    pub(crate) fn new() -> Result<PCState, PCError> {
+    pub(crate) fn new(config: &PCConfig) -> Result<PCState, PCError> {
+        // Nothing is persisted for the in-memory database, not even the root
+        let data_dir = match config.get_database() {
+            DatabaseKind::Memory => None,
+            _ => config.get_data_dir(),
+        };
         // Prepare the database to store the commits
-        let db = Box::new(BTreeDatabase::new(&INDEXES));
+        let db: Box<dyn Database> = match (config.get_database(), &data_dir) {
+            (DatabaseKind::Lmdb, Some(dir)) => open_lmdb(dir)?,
+            (DatabaseKind::Sqlite, Some(dir)) => open_sqlite(dir)?,
+            (DatabaseKind::Memory, _) => Box::new(BTreeDatabase::new(&INDEXES)),
+            (kind, None) => {
+                return Err(PCError::from(format!(
+                    "No data directory for the {:?} database",
+                    kind
+                )))
+            }
+        };
         let context_manager = ContextManager::new(Box::new(MerkleState::new(db.clone())));
-        let mut executor = Executor::new(vec![Box::new(
-            match StaticExecutionAdapter::new_adapter(
+        // An executor hands all its tasks to the same execution adapter, which
+        // runs one transaction at a time. The parallel scheduler feeds several
+        // executors to run transactions concurrently.
+        let execution_units = match config.get_scheduler_mode() {
+            SchedulerMode::Serial => 1,
+            SchedulerMode::Parallel => PARALLEL_EXECUTION_UNITS,
+        };
+        let mut executors = vec![];
+        for _ in 0..execution_units {
+            let execution_adapter = match StaticExecutionAdapter::new_adapter(
                 vec![Box::new(SawtoothToTransactHandlerAdapter::new(
                     ProduceConsumeHandler::new(),
                 ))],
@@ -42,22 +80,26 @@ impl PCState {
             ) {
                 Ok(execution_adapter) => execution_adapter,
                 Err(err) => return Err(PCError::from(err.to_string())),
-            },
-        )]);
-        match executor.start() {
-            Ok(_) => info!("Execution unit started"),
-            Err(err) => return Err(PCError::from(err.to_string())),
+            };
+            let mut executor = Executor::new(vec![Box::new(execution_adapter)]);
+            match executor.start() {
+                Ok(_) => info!("Execution unit started"),
+                Err(err) => return Err(PCError::from(err.to_string())),
+            }
+            executors.push(executor);
         }
 
         Ok(PCState {
             db,
             context_manager,
-            executor,
+            executors,
+            data_dir,
+            scheduler_mode: config.get_scheduler_mode(),
         })
     }
 
-    pub(crate) fn get_executor(&self) -> &Executor {
-        &self.executor
+    pub(crate) fn get_executors(&self) -> &[Executor] {
+        &self.executors
     }
 
     pub(crate) fn get_db(&self) -> Box<dyn Database> {
@@ -67,4 +109,77 @@ impl PCState {
     pub(crate) fn get_context_manager(&self) -> ContextManager {
         self.context_manager.clone()
     }
+
+    pub(crate) fn get_scheduler_mode(&self) -> SchedulerMode {
+        self.scheduler_mode
+    }
+
+    /// Directory holding the persisted state, `None` when running in memory.
+    pub(crate) fn get_data_dir(&self) -> Option<PathBuf> {
+        self.data_dir.clone()
+    }
+
+    /// Returns the last committed state root, if it was persisted in an
+    /// earlier run.
+    pub(crate) fn load_state_root(&self) -> Result<Option<String>, PCError> {
+        let path = match &self.data_dir {
+            Some(dir) => dir.join(STATE_ROOT_FILE_NAME),
+            None => return Ok(None),
+        };
+        if !path.exists() {
+            return Ok(None);
+        }
+        match fs::read_to_string(&path) {
+            Ok(root) => Ok(Some(root.trim().to_string())),
+            Err(err) => Err(PCError::from(err.to_string())),
+        }
+    }
+
+    /// Remembers the state root so that the next run resumes from it.
+    pub(crate) fn store_state_root(&self, state_root: &str) -> Result<(), PCError> {
+        let path = match &self.data_dir {
+            Some(dir) => dir.join(STATE_ROOT_FILE_NAME),
+            None => return Ok(()),
+        };
+        write_file(&path, state_root.as_bytes())
+    }
+}
+
+fn open_lmdb(dir: &Path) -> Result<Box<dyn Database>, PCError> {
+    if let Err(err) = fs::create_dir_all(dir) {
+        return Err(PCError::from(err.to_string()));
+    }
+    let context = match LmdbContext::new(&dir.join(LMDB_FILE_NAME), INDEXES.len(), None) {
+        Ok(context) => context,
+        Err(err) => return Err(PCError::from(err.to_string())),
+    };
+    match LmdbDatabase::new(context, &INDEXES) {
+        Ok(db) => {
+            info!("Using the LMDB database in {:?}", dir);
+            Ok(Box::new(db))
+        }
+        Err(err) => Err(PCError::from(err.to_string())),
+    }
+}
+
+fn open_sqlite(dir: &Path) -> Result<Box<dyn Database>, PCError> {
+    if let Err(err) = fs::create_dir_all(dir) {
+        return Err(PCError::from(err.to_string()));
+    }
+    let path = dir.join(SQLITE_FILE_NAME);
+    let path_str = match path.to_str() {
+        Some(path_str) => path_str,
+        None => return Err(PCError::from("The data directory is not a valid path")),
+    };
+    match SqliteDatabase::builder()
+        .with_path(path_str)
+        .with_indexes(&INDEXES)
+        .build()
+    {
+        Ok(db) => {
+            info!("Using the SQLite database {:?}", path);
+            Ok(Box::new(db))
+        }
+        Err(err) => Err(PCError::from(err.to_string())),
+    }
 }
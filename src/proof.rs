@@ -0,0 +1,276 @@
+// Copyright 2019 Arun S M
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::handler::compute_address;
+use crate::item_state::ItemState;
+use crate::pc_error::PCError;
+use crate::proto::proof::MerkleProof;
+use cbor::value::{Bytes, Key, Text, Value};
+use cbor::{Config, GenericDecoder};
+use protobuf::RepeatedField;
+use sha2::{Digest, Sha512};
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use transact::database::Database;
+
+/// Number of hex characters of the address consumed at each level of the tree
+const TOKEN_SIZE: usize = 2;
+
+/// Number of hex characters of an address
+const ADDRESS_SIZE: usize = 70;
+
+/// Value of a merkle node and the hashes of its children by address token
+type MerkleNode = (Option<Vec<u8>>, BTreeMap<String, String>);
+
+/// Collects the merkle nodes on the path from the state root down to the
+/// leaf of the item, the one owned by the public key if it is given. The
+/// proof can be checked with `verify_proof`, knowing only the state root.
+pub(crate) fn generate_proof(
+    db: Box<dyn Database>,
+    state_root: &str,
+    identifier: &str,
+    owner: Option<&str>,
+) -> Result<MerkleProof, PCError> {
+    let address = compute_address(owner, identifier);
+    let reader = match db.get_reader() {
+        Ok(reader) => reader,
+        Err(err) => return Err(PCError::from(err.to_string())),
+    };
+
+    let mut nodes = vec![];
+    let mut node_hash = state_root.to_string();
+    for token in tokenize_address(&address) {
+        let node_bytes = match reader.get(node_hash.as_bytes()) {
+            Ok(Some(node_bytes)) => node_bytes,
+            Ok(None) => return Err(PCError::from(format!("Missing merkle node {}", node_hash))),
+            Err(err) => return Err(PCError::from(err.to_string())),
+        };
+        let (_, mut children) = decode_node(&node_bytes)?;
+        node_hash = match children.remove(token) {
+            Some(child_hash) => child_hash,
+            None => {
+                return Err(PCError::from(format!(
+                    "{} is not in the state {}",
+                    identifier, state_root
+                )))
+            }
+        };
+        nodes.push(node_bytes);
+    }
+    match reader.get(node_hash.as_bytes()) {
+        Ok(Some(leaf_bytes)) => nodes.push(leaf_bytes),
+        Ok(None) => return Err(PCError::from(format!("Missing merkle node {}", node_hash))),
+        Err(err) => return Err(PCError::from(err.to_string())),
+    };
+
+    let mut proof = MerkleProof::new();
+    proof.set_state_root(state_root.to_string());
+    proof.set_address(address);
+    proof.set_nodes(RepeatedField::from_vec(nodes));
+    Ok(proof)
+}
+
+/// Checks the proof against the state root and returns the proven item,
+/// which must be stored at its address, the one owned by the public key if
+/// it is given. It needs neither the database nor the handler, only the
+/// hashing and node encoding of the merkle tree.
+pub(crate) fn verify_proof(
+    proof: &MerkleProof,
+    state_root: &str,
+    owner: Option<&str>,
+) -> Result<ItemState, PCError> {
+    // The address is read from an untrusted file
+    let address = proof.get_address();
+    if address.len() != ADDRESS_SIZE
+        || !address
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+    {
+        return Err(PCError::from("The proof has an invalid address"));
+    }
+    let tokens = tokenize_address(address);
+    let nodes = proof.get_nodes();
+    if nodes.len() != tokens.len() + 1 {
+        return Err(PCError::from("The proof does not reach the leaf"));
+    }
+    if hash_node(&nodes[0]) != state_root {
+        return Err(PCError::from("The proof does not start at the state root"));
+    }
+
+    for (depth, token) in tokens.iter().enumerate() {
+        let (_, children) = decode_node(&nodes[depth])?;
+        match children.get(*token) {
+            Some(child_hash) if *child_hash == hash_node(&nodes[depth + 1]) => (),
+            _ => {
+                return Err(PCError::from(format!(
+                    "The proof is broken at depth {}",
+                    depth + 1
+                )))
+            }
+        }
+    }
+
+    let item = match decode_node(&nodes[tokens.len()])? {
+        (Some(value), _) => ItemState::from_bytes(&value)?,
+        (None, _) => return Err(PCError::from("The leaf of the proof has no value")),
+    };
+    if compute_address(owner, &item.get_identifier()) != address {
+        return Err(PCError::from(format!(
+            "The proof is not for the item {} at its address",
+            item.get_identifier()
+        )));
+    }
+    Ok(item)
+}
+
+fn tokenize_address(address: &str) -> Vec<&str> {
+    (0..address.len())
+        .step_by(TOKEN_SIZE)
+        .map(|start| &address[start..(start + TOKEN_SIZE).min(address.len())])
+        .collect()
+}
+
+/// Node hashes are the first half of the SHA-512 of the encoded node
+fn hash_node(node_bytes: &[u8]) -> String {
+    let digest = Sha512::digest(node_bytes);
+    hex::encode(&digest[..digest.len() / 2])
+}
+
+/// Merkle nodes are CBOR maps, with the value under "v" and the hashes of
+/// the children, keyed by their address token, under "c".
+fn decode_node(node_bytes: &[u8]) -> Result<MerkleNode, PCError> {
+    let mut decoder = GenericDecoder::new(Config::default(), Cursor::new(node_bytes));
+    let mut node = match decoder.value() {
+        Ok(Value::Map(node)) => node,
+        Ok(_) => return Err(PCError::from("Invalid merkle node")),
+        Err(err) => return Err(PCError::from(err.to_string())),
+    };
+
+    let value = match node.remove(&Key::Text(Text::Text("v".to_string()))) {
+        Some(Value::Bytes(Bytes::Bytes(value))) => Some(value),
+        Some(Value::Null) | None => None,
+        Some(_) => return Err(PCError::from("Invalid merkle node value")),
+    };
+
+    let mut children = BTreeMap::new();
+    match node.remove(&Key::Text(Text::Text("c".to_string()))) {
+        Some(Value::Map(children_map)) => {
+            for (token, child_hash) in children_map {
+                match (token, child_hash) {
+                    (Key::Text(Text::Text(token)), Value::Text(Text::Text(child_hash))) => {
+                        children.insert(token, child_hash);
+                    }
+                    _ => return Err(PCError::from("Invalid merkle node child")),
+                }
+            }
+        }
+        None => (),
+        Some(_) => return Err(PCError::from("Invalid merkle node children")),
+    }
+
+    Ok((value, children))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batcher::Batcher;
+    use crate::config::{PCConfig, SchedulerMode};
+    use crate::handler::VERSION_2;
+    use crate::payload::ProduceConsumePayload;
+    use crate::pc_state::PCState;
+    use crate::scheduler::schedule;
+    use crate::state_handler::commit_state;
+    use crate::transaction::encode_payload;
+    use cylinder::secp256k1::Secp256k1Context;
+    use cylinder::Context;
+    use transact::state::merkle::{MerkleRadixTree, MerkleState};
+
+    #[test]
+    fn verify_proof_rejects_a_forged_proof() {
+        let state = PCState::new(&PCConfig::in_memory(SchedulerMode::Serial)).unwrap();
+        let statestore = MerkleState::new(state.get_db());
+        let genesis_root = MerkleRadixTree::new(state.get_db(), None)
+            .unwrap()
+            .get_merkle_root();
+        let context = Secp256k1Context::new();
+        let signer = context.new_signer(context.new_random_private_key());
+        let batcher = Batcher::new(signer, VERSION_2);
+        let payloads = [
+            "CREATE_ITEM apple kg",
+            "CREATE_ITEM pear kg",
+            "PRODUCE apple 4",
+            "PRODUCE pear 9",
+        ]
+        .iter()
+        .map(|line| {
+            let payload = ProduceConsumePayload::new(line).unwrap();
+            encode_payload(&payload, None, VERSION_2, false).unwrap()
+        })
+        .collect();
+        let batch = batcher.multi_txn(payloads).unwrap();
+        let result = schedule(&state, vec![batch], &genesis_root)
+            .unwrap()
+            .pop()
+            .unwrap();
+        let state_root = commit_state(&statestore, &genesis_root, result).unwrap();
+
+        let proof = generate_proof(state.get_db(), &state_root, "apple", None).unwrap();
+        let item = verify_proof(&proof, &state_root, None).unwrap();
+        assert_eq!(
+            (item.get_identifier().as_str(), item.get_quantity()),
+            ("apple", 4)
+        );
+        let failure = |forged: &MerkleProof| {
+            verify_proof(forged, &state_root, None)
+                .unwrap_err()
+                .to_string()
+        };
+
+        // The proof holds for its own state root only
+        let err = verify_proof(&proof, &genesis_root, None).unwrap_err();
+        assert!(err.to_string().contains("does not start at the state root"));
+
+        // A rewritten leaf no longer hashes to its parent's child
+        let mut forged = proof.clone();
+        let leaf = forged.mut_nodes().last_mut().unwrap();
+        let last = leaf.len() - 1;
+        leaf[last] ^= 1;
+        assert!(failure(&forged).contains("The proof is broken"));
+
+        // The pear proof claimed for the apple address follows another path
+        let pear = generate_proof(state.get_db(), &state_root, "pear", None).unwrap();
+        let mut forged = pear.clone();
+        forged.set_address(proof.get_address().to_string());
+        assert!(failure(&forged).contains("The proof is broken"));
+
+        // A path cut short does not reach the leaf
+        let mut forged = proof.clone();
+        forged.mut_nodes().pop();
+        assert!(failure(&forged).contains("does not reach the leaf"));
+
+        let mut forged = proof.clone();
+        forged.set_address(proof.get_address().to_uppercase());
+        assert!(failure(&forged).contains("invalid address"));
+
+        // A shared item is not the item of an owner
+        let owner = context
+            .new_signer(context.new_random_private_key())
+            .public_key()
+            .unwrap()
+            .as_hex();
+        let err = verify_proof(&proof, &state_root, Some(&owner)).unwrap_err();
+        assert!(err.to_string().contains("is not for the item apple"));
+    }
+}
@@ -0,0 +1,125 @@
+// Copyright 2019 Arun S M
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::pc_error::PCError;
+use std::str::FromStr;
+
+/// Quantities are fixed-point decimals, an integer number of units of
+/// 10^-scale. An item declares its scale, 0 for whole units, and at most
+/// this many decimal places as 10^18 still fits an i64.
+pub(crate) const MAX_SCALE: u32 = 18;
+
+/// Parses a non-negative decimal such as "12" or "2.750" into its value and
+/// scale, the scale being the number of digits after the point.
+pub(crate) fn parse_decimal(text: &str) -> Result<(i64, u32), PCError> {
+    let (whole, fraction) = match text.find('.') {
+        Some(point) => (&text[..point], &text[point + 1..]),
+        None => (text, ""),
+    };
+    let digits = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit());
+    if !digits(whole) || !(fraction.is_empty() || digits(fraction)) || text.ends_with('.') {
+        return Err(PCError::from(format!("Invalid quantity {:?}", text)));
+    }
+    let scale = fraction.len() as u32;
+    if scale > MAX_SCALE {
+        return Err(PCError::from(format!(
+            "At most {} decimal places are allowed",
+            MAX_SCALE
+        )));
+    }
+
+    let value = match i64::from_str(&format!("{}{}", whole, fraction)) {
+        Ok(value) => value,
+        Err(err) => return Err(PCError::from(err.to_string())),
+    };
+    Ok((value, scale))
+}
+
+/// Converts the value to another scale. Fails on an overflow, or when
+/// the value has more decimal places than the new scale can hold.
+pub(crate) fn rescale(value: i64, scale: u32, to_scale: u32) -> Option<i64> {
+    if to_scale >= scale {
+        10i64
+            .checked_pow(to_scale - scale)
+            .and_then(|factor| value.checked_mul(factor))
+    } else {
+        let factor = 10i64.checked_pow(scale - to_scale)?;
+        if value % factor == 0 {
+            Some(value / factor)
+        } else {
+            None
+        }
+    }
+}
+
+/// Formats the value with the decimal places of its scale
+pub(crate) fn format_decimal(value: i64, scale: u32) -> String {
+    if scale == 0 {
+        return value.to_string();
+    }
+    let digits = format!(
+        "{:0>width$}",
+        value.unsigned_abs(),
+        width = scale as usize + 1
+    );
+    let point = digits.len() - scale as usize;
+    format!(
+        "{}{}.{}",
+        if value < 0 { "-" } else { "" },
+        &digits[..point],
+        &digits[point..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_decimal_keeps_the_decimal_places() {
+        assert_eq!(parse_decimal("12").unwrap(), (12, 0));
+        assert_eq!(parse_decimal("2.750").unwrap(), (2750, 3));
+        assert!(parse_decimal("2.").is_err());
+        assert!(parse_decimal(".5").is_err());
+        assert!(parse_decimal("-1").is_err());
+    }
+
+    #[test]
+    fn parse_decimal_rejects_an_overflow_at_max_scale() {
+        assert_eq!(
+            parse_decimal("9.223372036854775807").unwrap(),
+            (i64::MAX, MAX_SCALE)
+        );
+        assert!(parse_decimal("9.223372036854775808").is_err());
+        assert!(parse_decimal("10.000000000000000000").is_err());
+        assert!(parse_decimal("1.0000000000000000000").is_err());
+    }
+
+    #[test]
+    fn rescale_rejects_a_lost_precision_or_an_overflow() {
+        assert_eq!(rescale(15, 0, 2), Some(1500));
+        assert_eq!(rescale(1500, 3, 1), Some(15));
+        assert_eq!(rescale(15, 1, 0), None);
+        assert_eq!(rescale(i64::MAX, 0, 1), None);
+        assert_eq!(rescale(1, 0, MAX_SCALE + 1), None);
+    }
+
+    #[test]
+    fn format_decimal_writes_negative_values() {
+        assert_eq!(format_decimal(-7, 0), "-7");
+        assert_eq!(format_decimal(-5, 2), "-0.05");
+        assert_eq!(format_decimal(-1250, 3), "-1.250");
+        assert_eq!(format_decimal(i64::MIN, MAX_SCALE), "-9.223372036854775808");
+    }
+}
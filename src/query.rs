@@ -0,0 +1,146 @@
+// Copyright 2019 Arun S M
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::handler::{compute_address, get_owner_prefix, get_produce_consume_prefix};
+use crate::item_state::ItemState;
+use crate::pc_error::PCError;
+use transact::database::Database;
+use transact::state::merkle::{MerkleRadixTree, MerkleState};
+use transact::state::Read;
+
+/// Number of items printed per page by the `LIST` command
+pub(crate) const PAGE_SIZE: usize = 20;
+
+/// Reads the quantity of an item at the state root and its scale, without
+/// submitting a transaction. An item that was never produced has no
+/// quantity. The item owned by the public key is read when an owner is
+/// passed.
+pub(crate) fn query_quantity(
+    state: &MerkleState,
+    state_root: &str,
+    identifier: &str,
+    owner: Option<&str>,
+) -> Result<(i64, u32), PCError> {
+    match read_item(state, state_root, identifier, owner)? {
+        Some(item) => Ok((item.get_quantity(), item.get_scale())),
+        None => Ok((0, 0)),
+    }
+}
+
+/// Reads an item at the state root, none if it is not in the state. The
+/// item owned by the public key is read when an owner is passed.
+pub(crate) fn read_item(
+    state: &MerkleState,
+    state_root: &str,
+    identifier: &str,
+    owner: Option<&str>,
+) -> Result<Option<ItemState>, PCError> {
+    let address = compute_address(owner, identifier);
+    let mut values = match state.get(&state_root.to_string(), std::slice::from_ref(&address)) {
+        Ok(values) => values,
+        Err(err) => return Err(PCError::from(err.to_string())),
+    };
+    match values.remove(&address) {
+        Some(present) => Ok(Some(ItemState::from_bytes(&present)?)),
+        None => Ok(None),
+    }
+}
+
+/// Reads every item at the state root, shared and owned, with its address.
+pub(crate) fn read_items(
+    db: Box<dyn Database>,
+    state_root: &str,
+) -> Result<Vec<(String, ItemState)>, PCError> {
+    read_leaves(
+        db,
+        state_root,
+        &get_produce_consume_prefix(),
+        |_| true,
+        0,
+        None,
+    )
+}
+
+/// Returns one page of the items at the state root and whether more pages
+/// follow it. Pages are numbered from 1. Only the items owned by the public
+/// key are listed when an owner is passed, the shared items otherwise.
+pub(crate) fn list_items(
+    db: Box<dyn Database>,
+    state_root: &str,
+    page: usize,
+    owner: Option<&str>,
+) -> Result<(Vec<(String, ItemState)>, bool), PCError> {
+    let prefix = match owner {
+        Some(owner) => get_owner_prefix(owner),
+        None => get_produce_consume_prefix(),
+    };
+    let listed_owner = owner.unwrap_or_default();
+    let start = (page.max(1) - 1) * PAGE_SIZE;
+    // Read one item past the page to know if there is a next page
+    let mut items = read_leaves(
+        db,
+        state_root,
+        &prefix,
+        |item| item.get_owner() == listed_owner,
+        start,
+        Some(PAGE_SIZE + 1),
+    )?;
+    let has_more = items.len() > PAGE_SIZE;
+    items.truncate(PAGE_SIZE);
+    Ok((items, has_more))
+}
+
+/// Walks the leaves under the address prefix at the state root, returning
+/// the address and the decoded state of the items that are kept. The items
+/// are skipped and limited after they are filtered.
+fn read_leaves<F>(
+    db: Box<dyn Database>,
+    state_root: &str,
+    prefix: &str,
+    keep: F,
+    skip: usize,
+    limit: Option<usize>,
+) -> Result<Vec<(String, ItemState)>, PCError>
+where
+    F: Fn(&ItemState) -> bool,
+{
+    let tree = match MerkleRadixTree::new(db, Some(state_root)) {
+        Ok(tree) => tree,
+        Err(err) => return Err(PCError::from(err.to_string())),
+    };
+    let leaves = match tree.leaves(Some(prefix)) {
+        Ok(leaves) => leaves,
+        Err(err) => return Err(PCError::from(err.to_string())),
+    };
+    let mut items = vec![];
+    let mut skipped = 0;
+    for leaf in leaves {
+        if limit.is_some_and(|limit| items.len() >= limit) {
+            break;
+        }
+        let (address, item) = match leaf {
+            Ok((address, present)) => (address, ItemState::from_bytes(&present)?),
+            Err(err) => return Err(PCError::from(err.to_string())),
+        };
+        if !keep(&item) {
+            continue;
+        }
+        if skipped < skip {
+            skipped += 1;
+            continue;
+        }
+        items.push((address, item));
+    }
+    Ok(items)
+}
@@ -12,23 +12,39 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::config::SchedulerMode;
+use crate::parallel_scheduler::ParallelScheduler;
 use crate::pc_error::PCError;
 use crate::pc_state::PCState;
 use transact::protocol::batch::BatchPair;
 use transact::scheduler::serial::SerialScheduler;
 use transact::scheduler::{BatchExecutionResult, Scheduler};
 
+/// Executes the batches in a single run of the scheduler of the state's
+/// mode, each batch on the state produced by the valid batches before it.
+/// Returns the result of every batch, in the order of the batches.
+/// The parallel scheduler runs the transactions concurrently when their
+/// declared input and output addresses do not overlap, conflicting ones
+/// are executed in the order of the batches as the serial scheduler does.
 pub(crate) fn schedule(
     state: &PCState,
-    batch: BatchPair,
+    batches: Vec<BatchPair>,
     state_root: &str,
-) -> Result<BatchExecutionResult, PCError> {
-    let context_manager = state.get_context_manager();
-    let mut scheduler =
-        match SerialScheduler::new(Box::new(context_manager), state_root.to_string()) {
-            Ok(created_scheduler) => created_scheduler,
-            Err(err) => return Err(PCError::from(err.to_string())),
-        };
+) -> Result<Vec<BatchExecutionResult>, PCError> {
+    let context_manager = Box::new(state.get_context_manager());
+    let created_scheduler: Result<Box<dyn Scheduler>, _> = match state.get_scheduler_mode() {
+        SchedulerMode::Serial => SerialScheduler::new(context_manager, state_root.to_string())
+            .map(|scheduler| Box::new(scheduler) as Box<dyn Scheduler>),
+        SchedulerMode::Parallel => Ok(Box::new(ParallelScheduler::new(
+            context_manager,
+            state_root.to_string(),
+            state.get_executors().len(),
+        )) as Box<dyn Scheduler>),
+    };
+    let mut scheduler = match created_scheduler {
+        Ok(created_scheduler) => created_scheduler,
+        Err(err) => return Err(PCError::from(err.to_string())),
+    };
 
     let (sender, receiver) = std::sync::mpsc::channel();
 
@@ -39,39 +55,163 @@ pub(crate) fn schedule(
         Err(err) => return Err(PCError::from(err.to_string())),
     };
 
-    match scheduler.add_batch(batch) {
-        Ok(_) => info!("Successfully added the batch"),
-        Err(err) => return Err(PCError::from(err.to_string())),
-    };
+    let batch_ids: Vec<String> = batches
+        .iter()
+        .map(|batch| batch.batch().header_signature().to_string())
+        .collect();
+    for batch in batches {
+        match scheduler.add_batch(batch) {
+            Ok(_) => info!("Successfully added the batch"),
+            Err(err) => return Err(PCError::from(err.to_string())),
+        };
+    }
 
     match scheduler.finalize() {
         Ok(_) => info!("Successfully finalized the scheduler"),
         Err(err) => return Err(PCError::from(err.to_string())),
     };
 
-    // Run the scheduler
-    let task_iterator = match scheduler.take_task_iterator() {
-        Ok(iterator) => iterator,
-        Err(err) => return Err(PCError::from(err.to_string())),
-    };
+    // Run the scheduler, every executor takes tasks from it
+    for executor in state.get_executors() {
+        let task_iterator = match scheduler.take_task_iterator() {
+            Ok(iterator) => iterator,
+            Err(err) => return Err(PCError::from(err.to_string())),
+        };
+        let notifier = match scheduler.new_notifier() {
+            Ok(success) => success,
+            Err(err) => return Err(PCError::from(err.to_string())),
+        };
+        match executor.execute(task_iterator, notifier) {
+            Ok(_) => info!("Successfully executed the scheduled tasks"),
+            Err(err) => return Err(PCError::from(err.to_string())),
+        };
+    }
 
-    let executor = state.get_executor();
-    let notifier = match scheduler.new_notifier() {
-        Ok(success) => success,
-        Err(err) => return Err(PCError::from(err.to_string())),
-    };
-    match executor.execute(task_iterator, notifier) {
-        Ok(_) => info!("Successfully executed the scheduled tasks"),
-        Err(err) => return Err(PCError::from(err.to_string())),
-    };
+    // Receive the result of every batch, the end of the results is
+    // signalled with none
+    let mut results = vec![];
+    loop {
+        match receiver.recv() {
+            Ok(Some(found_result)) => results.push(found_result),
+            Ok(None) => break,
+            Err(err) => return Err(PCError::from(err.to_string())),
+        }
+    }
+    if results.len() != batch_ids.len() {
+        return Err(PCError::from(format!(
+            "Unexpected error, found {} results for {} batches",
+            results.len(),
+            batch_ids.len()
+        )));
+    }
+    results.sort_by_key(|result| {
+        batch_ids
+            .iter()
+            .position(|batch_id| batch_id == result.batch.batch().header_signature())
+    });
+    Ok(results)
+}
 
-    // Receive the result from the execution
-    let result = match receiver.recv() {
-        Ok(success) => success,
-        Err(err) => return Err(PCError::from(err.to_string())),
-    };
-    match result {
-        Some(found_result) => Ok(found_result),
-        None => Err(PCError::from("Unexpected error, no result found")),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batcher::Batcher;
+    use crate::config::PCConfig;
+    use crate::handler::VERSION_2;
+    use crate::payload::ProduceConsumePayload;
+    use crate::state_handler::commit_state;
+    use crate::transaction::encode_payload;
+    use cylinder::secp256k1::Secp256k1Context;
+    use cylinder::Context;
+    use transact::protocol::receipt::TransactionResult;
+    use transact::state::merkle::{MerkleRadixTree, MerkleState};
+
+    /// Runs the batches on a fresh state with the scheduler of the mode
+    /// and commits the valid ones in order. Returns the validity of every
+    /// receipt of every batch and the final state root.
+    fn commit_batches(mode: SchedulerMode, batches: &[&[&str]]) -> (Vec<Vec<bool>>, String) {
+        let state = PCState::new(&PCConfig::in_memory(mode)).unwrap();
+        let statestore = MerkleState::new(state.get_db());
+        let mut state_root = MerkleRadixTree::new(state.get_db(), None)
+            .unwrap()
+            .get_merkle_root();
+
+        let context = Secp256k1Context::new();
+        let signer = context.new_signer(context.new_random_private_key());
+        let batcher = Batcher::new(signer, VERSION_2);
+        let batches = batches
+            .iter()
+            .map(|lines| {
+                let payloads = lines
+                    .iter()
+                    .map(|line| {
+                        let payload = ProduceConsumePayload::new(line).unwrap();
+                        encode_payload(&payload, None, VERSION_2, false).unwrap()
+                    })
+                    .collect();
+                batcher.multi_txn(payloads).unwrap()
+            })
+            .collect();
+
+        let mut validity = vec![];
+        for result in schedule(&state, batches, &state_root).unwrap() {
+            let valid: Vec<bool> = result
+                .receipts
+                .iter()
+                .map(|receipt| match receipt.transaction_result {
+                    TransactionResult::Valid { .. } => true,
+                    TransactionResult::Invalid { .. } => false,
+                })
+                .collect();
+            if valid.iter().all(|valid| *valid) {
+                state_root = commit_state(&statestore, &state_root, result).unwrap();
+            }
+            validity.push(valid);
+        }
+        (validity, state_root)
+    }
+
+    #[test]
+    fn parallel_scheduler_commits_the_serial_state_root() {
+        // Transactions on distinct items as well as conflicting ones
+        let batches: &[&[&str]] = &[&[
+            "CREATE_ITEM apple kg",
+            "CREATE_ITEM pear kg",
+            "CREATE_ITEM plum kg",
+            "PRODUCE apple 10",
+            "PRODUCE pear 5 2030-01-31",
+            "PRODUCE plum 7",
+            "TRANSFER apple pear 3",
+            "CONSUME pear 6 FEFO",
+            "CONSUME plum 7",
+        ]];
+        assert_eq!(
+            commit_batches(SchedulerMode::Serial, batches),
+            commit_batches(SchedulerMode::Parallel, batches)
+        );
+    }
+
+    #[test]
+    fn parallel_scheduler_skips_an_invalid_batch() {
+        // The second batch over-consumes, the third reads the first only.
+        // The serial scheduler of transact executes a batch on the changes
+        // of the batch before it even when that one is invalid, so it is
+        // given the valid batches alone.
+        let valid: &[&str] = &[
+            "CREATE_ITEM apple kg",
+            "CREATE_ITEM pear kg",
+            "PRODUCE apple 10",
+        ];
+        let invalid: &[&str] = &["PRODUCE pear 4", "CONSUME apple 11"];
+        let later: &[&str] = &["CONSUME apple 4", "PRODUCE pear 2"];
+
+        let (validity, parallel_root) =
+            commit_batches(SchedulerMode::Parallel, &[valid, invalid, later]);
+        assert_eq!(
+            validity,
+            vec![vec![true, true, true], vec![false, false], vec![true, true]]
+        );
+        let (_, serial_root) = commit_batches(SchedulerMode::Serial, &[valid, later]);
+        assert_eq!(parallel_root, serial_root);
     }
 }
@@ -13,51 +13,264 @@
 // limitations under the License.
 
 use crate::pc_error::PCError;
+use crate::proto::receipt::{LotDraw, LotDraws};
+use protobuf::Message;
 use transact::protocol::receipt::{StateChange, TransactionReceipt, TransactionResult};
 use transact::scheduler::BatchExecutionResult;
 use transact::state::merkle::MerkleState;
 use transact::state::StateChange as ChangeSet;
-use transact::state::Write;
+use transact::state::{Read, Write};
 
+use std::collections::BTreeMap;
+
+/// Commits the state changes of every transaction in the batch on top of
+/// the current root at once, returning the new state root. Nothing is
+/// committed if any transaction of the batch is invalid.
 pub(crate) fn commit_state(
     state: &MerkleState,
     cur_root: &str,
-    mut result: BatchExecutionResult,
+    result: BatchExecutionResult,
 ) -> Result<String, PCError> {
-    // Extract the transaction result
-    let txn_result = match result.receipts.pop() {
-        Some(result) => result.transaction_result,
-        None => return Err(PCError::from("Unable to find the result")),
-    };
-
-    // Extract the transaction execution result
-    let mut state_change_vector = match txn_result {
-        TransactionResult::Valid { state_changes, .. } => state_changes,
-        TransactionResult::Invalid { error_message, .. } => {
-            return Err(PCError::from(error_message))
+    if result.receipts.is_empty() {
+        return Err(PCError::from("Unable to find the result"));
+    }
+
+    // Fold the changes of the transactions in the order of the batch, the
+    // last set or delete of an address is the one committed
+    let mut changes: BTreeMap<String, ChangeSet> = BTreeMap::new();
+    for receipt in result.receipts {
+        match receipt.transaction_result {
+            TransactionResult::Valid { state_changes, .. } => {
+                for state_change in state_changes {
+                    match state_change {
+                        StateChange::Set { key, value } => {
+                            changes.insert(key.clone(), ChangeSet::Set { key, value })
+                        }
+                        StateChange::Delete { key } => {
+                            changes.insert(key.clone(), ChangeSet::Delete { key })
+                        }
+                    };
+                }
+            }
+            TransactionResult::Invalid { error_message, .. } => {
+                return Err(PCError::from(error_message))
+            }
+        }
+    }
+
+    if changes.is_empty() {
+        return Err(PCError::from(
+            "Unable to find the receipt for the transaction",
+        ));
+    }
+
+    // An address set and deleted again within the batch is not in the
+    // current root, the merkle state refuses to delete it
+    let deleted = changes
+        .values()
+        .filter_map(|change| match change {
+            ChangeSet::Delete { key } => Some(key.clone()),
+            ChangeSet::Set { .. } => None,
+        })
+        .collect::<Vec<String>>();
+    if !deleted.is_empty() {
+        let existing = match state.get(&cur_root.to_string(), &deleted) {
+            Ok(existing) => existing,
+            Err(err) => return Err(PCError::from(err.to_string())),
+        };
+        for key in deleted {
+            if !existing.contains_key(&key) {
+                changes.remove(&key);
+            }
+        }
+    }
+
+    let changeset = changes.into_values().collect::<Vec<ChangeSet>>();
+    match state.commit(&cur_root.to_string(), &changeset) {
+        Ok(state_id) => Ok(state_id),
+        Err(err) => Err(PCError::from(err.to_string())),
+    }
+}
+
+/// What a transaction of an executed batch did, read from its receipt.
+#[derive(Debug)]
+pub(crate) struct TxnReport {
+    transaction_id: String,
+    error_message: Option<String>,
+    state_changes: Vec<StateChange>,
+    data: Vec<Vec<u8>>,
+}
+
+impl TxnReport {
+    fn from_receipt(receipt: &TransactionReceipt) -> TxnReport {
+        let (error_message, state_changes, data) = match &receipt.transaction_result {
+            TransactionResult::Valid {
+                state_changes,
+                data,
+                ..
+            } => (None, state_changes.clone(), data.clone()),
+            TransactionResult::Invalid { error_message, .. } => {
+                (Some(error_message.clone()), vec![], vec![])
+            }
+        };
+        TxnReport {
+            transaction_id: receipt.transaction_id.clone(),
+            error_message,
+            state_changes,
+            data,
         }
-    };
-
-    let state_changes = match state_change_vector.pop() {
-        Some(state_change) => state_change,
-        None => {
-            return Err(PCError::from(
-                "Unable to find the receipt for the transaction",
-            ))
+    }
+
+    /// Header signature of the transaction
+    pub(crate) fn get_transaction_id(&self) -> &str {
+        &self.transaction_id
+    }
+
+    pub(crate) fn is_valid(&self) -> bool {
+        self.error_message.is_none()
+    }
+
+    /// Reason the transaction is invalid, if it is
+    pub(crate) fn get_error_message(&self) -> Option<&str> {
+        self.error_message.as_deref()
+    }
+
+    pub(crate) fn get_state_changes(&self) -> &[StateChange] {
+        &self.state_changes
+    }
+
+    /// Lots the transaction drew from, decoded from its receipt data
+    pub(crate) fn get_lot_draws(&self) -> Vec<LotDraw> {
+        let mut draws = vec![];
+        for data in &self.data {
+            match LotDraws::parse_from_bytes(data) {
+                Ok(lot_draws) => draws.extend(lot_draws.get_draws().iter().cloned()),
+                Err(err) => warn!("Unreadable receipt data: {:?}", err),
+            }
         }
-    };
+        draws
+    }
+}
 
-    let (key, value) = match state_changes {
-        StateChange::Set { key, value } => (key, value),
-        _ => return Err(PCError::from("Found a delete operation")),
-    };
+/// Reports of the transactions of the batch that were executed, in the
+/// order of the batch. The transactions after an invalid one may have no
+/// receipt.
+pub(crate) fn txn_reports(result: &BatchExecutionResult) -> Vec<TxnReport> {
+    result
+        .receipts
+        .iter()
+        .map(TxnReport::from_receipt)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batcher::Batcher;
+    use crate::config::{PCConfig, SchedulerMode};
+    use crate::handler::VERSION_2;
+    use crate::payload::ProduceConsumePayload;
+    use crate::pc_state::PCState;
+    use crate::query::read_item;
+    use crate::scheduler::schedule;
+    use crate::transaction::encode_payload;
+    use cylinder::secp256k1::Secp256k1Context;
+    use cylinder::Context;
+    use transact::state::merkle::MerkleRadixTree;
+
+    /// Schedules the lines as one batch on top of the state root and
+    /// commits it, the lines marked with `true` delete the items they
+    /// consume down to nothing.
+    fn commit_lines(
+        state: &PCState,
+        state_root: &str,
+        lines: &[(&str, bool)],
+    ) -> Result<String, PCError> {
+        let context = Secp256k1Context::new();
+        let signer = context.new_signer(context.new_random_private_key());
+        let batcher = Batcher::new(signer, VERSION_2);
+        let payloads = lines
+            .iter()
+            .map(|(line, delete_empty)| {
+                let payload = ProduceConsumePayload::new(line).unwrap();
+                encode_payload(&payload, None, VERSION_2, *delete_empty).unwrap()
+            })
+            .collect();
+        let batch = batcher.multi_txn(payloads).unwrap();
+        let result = schedule(state, vec![batch], state_root)
+            .unwrap()
+            .pop()
+            .unwrap();
+        commit_state(&MerkleState::new(state.get_db()), state_root, result)
+    }
 
-    let changeset = ChangeSet::Set { key, value };
+    #[test]
+    fn decimal_quantities_are_committed_in_the_scale_of_the_item() {
+        let state = PCState::new(&PCConfig::in_memory(SchedulerMode::Serial)).unwrap();
+        let statestore = MerkleState::new(state.get_db());
+        let genesis_root = MerkleRadixTree::new(state.get_db(), None)
+            .unwrap()
+            .get_merkle_root();
+        let state_root = commit_lines(
+            &state,
+            &genesis_root,
+            &[
+                ("CREATE_ITEM apple kg scale=3", false),
+                ("PRODUCE apple 10.25", false),
+                ("CONSUME apple 0.125", false),
+            ],
+        )
+        .unwrap();
+        let item = read_item(&statestore, &state_root, "apple", None)
+            .unwrap()
+            .unwrap();
+        assert_eq!((item.get_quantity(), item.get_scale()), (10_125, 3));
 
-    let commit_result = match state.commit(&cur_root.to_string(), &[changeset]) {
-        Ok(state_id) => state_id,
-        Err(err) => return Err(PCError::from(err.to_string())),
-    };
+        // More decimal places than the item has fail the whole batch, the
+        // other transactions are not committed either
+        let err = commit_lines(
+            &state,
+            &state_root,
+            &[("PRODUCE apple 0.0001", false), ("PRODUCE apple 1", false)],
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("decimal places"));
+    }
 
-    Ok(commit_result)
+    #[test]
+    fn the_last_change_of_an_address_in_the_batch_is_committed() {
+        let state = PCState::new(&PCConfig::in_memory(SchedulerMode::Serial)).unwrap();
+        let statestore = MerkleState::new(state.get_db());
+        let genesis_root = MerkleRadixTree::new(state.get_db(), None)
+            .unwrap()
+            .get_merkle_root();
+        let state_root = commit_lines(
+            &state,
+            &genesis_root,
+            &[("CREATE_ITEM apple kg", false), ("PRODUCE apple 4", false)],
+        )
+        .unwrap();
+
+        // The apple is updated before it is deleted, the pear is set and
+        // deleted within the batch and is not in the state it is committed
+        // on
+        let state_root = commit_lines(
+            &state,
+            &state_root,
+            &[
+                ("CREATE_ITEM pear kg", false),
+                ("PRODUCE pear 2", false),
+                ("CONSUME pear 2", true),
+                ("CONSUME apple 3", false),
+                ("CONSUME apple 1", true),
+            ],
+        )
+        .unwrap();
+        for identifier in &["pear", "apple"] {
+            assert!(read_item(&statestore, &state_root, identifier, None)
+                .unwrap()
+                .is_none());
+        }
+        assert_eq!(state_root, genesis_root);
+    }
 }
@@ -12,62 +12,341 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::input::usr_input;
-use crate::payload::ProduceConsumePayload;
+use crate::batcher::TxnPayload;
+use crate::handler::{compute_address, VERSION, VERSION_2};
+use crate::item_state::DrawOrder;
+use crate::payload::{Command, ProduceConsumePayload};
 use crate::pc_error::PCError;
 use crate::proto::action::{Action, Action_Command};
-use protobuf::Message;
-use sha2::{Digest, Sha512};
+use crate::proto::action_v2::{ActionV2, Operation, Operation_Command, Operation_DrawOrder};
+use crate::quantity::format_decimal;
+use crate::query::read_item;
+use protobuf::{Message, RepeatedField};
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use transact::state::merkle::MerkleState;
 
-/// This method returns the payload if any from the user.
-/// If user interrupts the execution, it's returned here.
-pub(crate) fn transaction_payload() -> Result<(Vec<u8>, Vec<Vec<u8>>, Vec<Vec<u8>>), PCError> {
-    // Read input from the user, compose into the payload bytes
+/// This method returns the payload for the command entered by the user.
+/// The items are owned by the signer when its public key is passed as the
+/// owner. A version 1.0 payload may only touch the items already at the
+/// state root.
+pub(crate) fn transaction_payload(
+    usr_input: &str,
+    owner: Option<&str>,
+    family_version: &str,
+    delete_empty: bool,
+    state: &MerkleState,
+    state_root: &str,
+) -> Result<TxnPayload, PCError> {
+    // Compose the user input into the payload bytes
     // Send it back to the caller.
-    let usr_input = match usr_input() {
-        Ok(read_line) => read_line,
-        Err(err) => return Err(err),
-    };
-    let pc_payload = match ProduceConsumePayload::new(&usr_input) {
+    let pc_payload = match ProduceConsumePayload::new(usr_input) {
         Ok(read_payload) => read_payload,
         Err(err) => return Err(err),
     };
 
+    // debug statements
+    print_payload(&pc_payload);
+
+    let encoded = encode_payload(&pc_payload, owner, family_version, delete_empty)?;
+    if family_version == VERSION {
+        check_known_items(&pc_payload, owner, state, state_root)?;
+    }
+    Ok(encoded)
+}
+
+/// The handler registers the unknown items of a version 1.0 transaction on
+/// their first update, so they are rejected before the transaction is
+/// submitted.
+fn check_known_items(
+    pc_payload: &ProduceConsumePayload,
+    owner: Option<&str>,
+    state: &MerkleState,
+    state_root: &str,
+) -> Result<(), PCError> {
+    let mut identifiers = BTreeSet::new();
+    collect_identifiers(pc_payload, &mut identifiers);
+    for identifier in identifiers {
+        if read_item(state, state_root, &identifier, owner)?.is_none() {
+            return Err(PCError::from(format!(
+                "Unknown item {}, it must be created first with the family version {}",
+                identifier, VERSION_2
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Serializes the payload in the schema of the family version, returning
+/// it with the input and output addresses of the transaction. With
+/// `delete_empty` the items consumed to zero are deleted from the state.
+pub(crate) fn encode_payload(
+    pc_payload: &ProduceConsumePayload,
+    owner: Option<&str>,
+    family_version: &str,
+    delete_empty: bool,
+) -> Result<TxnPayload, PCError> {
     // Get the raw bytes of the payload that can be sent to the handler
-    let mut payload = Action::new();
-    payload.set_command(pc_payload.get_command());
-    payload.set_identifier(pc_payload.get_identifier());
-    payload.set_quantity(pc_payload.get_quantity());
+    let encoded = if family_version == VERSION {
+        if delete_empty {
+            return Err(PCError::from(format!(
+                "Deleting empty items needs the family version {}",
+                VERSION_2
+            )));
+        }
+        let mut payload = to_action(pc_payload)?;
+        payload.set_owner_scoped(owner.is_some());
+        payload.write_to_bytes()
+    } else {
+        let mut payload = to_action_v2(pc_payload)?;
+        payload.set_owner_scoped(owner.is_some());
+        payload.set_delete_empty(delete_empty);
+        payload.write_to_bytes()
+    };
+    let payload_bytes = match encoded {
+        Ok(bytes) => bytes,
+        Err(err) => return Err(PCError::from(err.to_string())),
+    };
+    // The transaction reads and writes every item touched by its operations
+    let mut identifiers = BTreeSet::new();
+    collect_identifiers(pc_payload, &mut identifiers);
+    let addresses: Vec<Vec<u8>> = identifiers
+        .iter()
+        .map(|identifier| item_address(owner, identifier))
+        .collect();
+    Ok((payload_bytes, addresses.clone(), addresses))
+}
 
-    // debug statements
+/// The version 1.0 `Action` schema is frozen, an operation it cannot carry
+/// needs the family version 2.0
+fn to_action(pc_payload: &ProduceConsumePayload) -> Result<Action, PCError> {
+    let mut action = Action::new();
+    action.set_command(match pc_payload.get_command() {
+        Command::Produce => Action_Command::PRODUCE,
+        Command::Consume => Action_Command::CONSUME,
+        Command::Transfer => Action_Command::TRANSFER,
+        Command::Multi => Action_Command::MULTI,
+        command => {
+            return Err(PCError::from(format!(
+                "The {:?} command needs the family version {}",
+                command, VERSION_2
+            )))
+        }
+    });
+    if pc_payload.get_scale() > 0 {
+        return Err(PCError::from(format!(
+            "Decimal quantities need the family version {}",
+            VERSION_2
+        )));
+    }
+    if pc_payload.get_expiry().is_some() || pc_payload.get_draw_order() != DrawOrder::Fifo {
+        return Err(PCError::from(format!(
+            "Lot expiry and FEFO need the family version {}",
+            VERSION_2
+        )));
+    }
+    let quantity = match i32::try_from(pc_payload.get_quantity()) {
+        Ok(quantity) => quantity,
+        Err(err) => return Err(PCError::from(err.to_string())),
+    };
+    action.set_identifier(pc_payload.get_identifier());
+    action.set_quantity(quantity);
+    action.set_destination(pc_payload.get_destination());
+    let mut operations = vec![];
+    for operation in pc_payload.get_operations() {
+        operations.push(to_action(operation)?);
+    }
+    action.set_operations(RepeatedField::from_vec(operations));
+    Ok(action)
+}
+
+/// Version 2.0 payloads are always a list of operations
+fn to_action_v2(pc_payload: &ProduceConsumePayload) -> Result<ActionV2, PCError> {
+    let payloads = match pc_payload.get_command() {
+        Command::Multi => pc_payload.get_operations().iter().collect(),
+        _ => vec![pc_payload],
+    };
+    let mut operations = vec![];
+    for operation in payloads {
+        operations.push(to_operation(operation)?);
+    }
+    let mut action = ActionV2::new();
+    action.set_operations(RepeatedField::from_vec(operations));
+    Ok(action)
+}
+
+/// The operations of a version 2.0 payload are a flat list, they cannot
+/// nest a MULTI command
+fn to_operation(pc_payload: &ProduceConsumePayload) -> Result<Operation, PCError> {
+    let mut operation = Operation::new();
+    operation.set_command(match pc_payload.get_command() {
+        Command::Produce => Operation_Command::PRODUCE,
+        Command::Consume => Operation_Command::CONSUME,
+        Command::Transfer => Operation_Command::TRANSFER,
+        Command::Reserve => Operation_Command::RESERVE,
+        Command::Release => Operation_Command::RELEASE,
+        Command::Commit => Operation_Command::COMMIT,
+        Command::CreateItem => Operation_Command::CREATE_ITEM,
+        Command::Multi => {
+            return Err(PCError::from(
+                "A MULTI command cannot be nested in another one",
+            ))
+        }
+    });
+    operation.set_identifier(pc_payload.get_identifier());
+    operation.set_destination(pc_payload.get_destination());
+    operation.set_quantity(pc_payload.get_quantity());
+    operation.set_scale(pc_payload.get_scale());
+    operation.set_reservation(pc_payload.get_reservation());
+    operation.set_unit(pc_payload.get_unit());
+    operation.set_description(pc_payload.get_description());
+    operation.set_capacity(pc_payload.get_capacity().unwrap_or(0));
+    operation.set_expiry(pc_payload.get_expiry().unwrap_or_default());
+    operation.set_draw_order(match pc_payload.get_draw_order() {
+        DrawOrder::Fifo => Operation_DrawOrder::FIFO,
+        DrawOrder::Fefo => Operation_DrawOrder::FEFO,
+    });
+    Ok(operation)
+}
+
+/// A transfer touches both the source and the destination
+fn collect_identifiers(pc_payload: &ProduceConsumePayload, identifiers: &mut BTreeSet<String>) {
+    match pc_payload.get_command() {
+        Command::Produce
+        | Command::Consume
+        | Command::Reserve
+        | Command::Release
+        | Command::Commit
+        | Command::CreateItem => {
+            identifiers.insert(pc_payload.get_identifier());
+        }
+        Command::Transfer => {
+            identifiers.insert(pc_payload.get_identifier());
+            identifiers.insert(pc_payload.get_destination());
+        }
+        Command::Multi => {
+            for operation in pc_payload.get_operations() {
+                collect_identifiers(operation, identifiers);
+            }
+        }
+    }
+}
+
+fn print_payload(pc_payload: &ProduceConsumePayload) {
     match pc_payload.get_command() {
-        Action_Command::PRODUCE => {
+        Command::Produce => {
             println!(
                 "Producing {} quantity of {}",
-                pc_payload.get_quantity(),
+                format_decimal(pc_payload.get_quantity(), pc_payload.get_scale()),
+                pc_payload.get_identifier()
+            );
+            if let Some(expiry) = pc_payload.get_expiry() {
+                println!("The lot expires on {}", expiry);
+            }
+        }
+        Command::Consume => {
+            println!(
+                "Consuming {} quantity of {} in {} order",
+                format_decimal(pc_payload.get_quantity(), pc_payload.get_scale()),
+                pc_payload.get_identifier(),
+                match pc_payload.get_draw_order() {
+                    DrawOrder::Fifo => "FIFO",
+                    DrawOrder::Fefo => "FEFO",
+                }
+            );
+        }
+        Command::Transfer => {
+            println!(
+                "Transferring {} quantity of {} to {}",
+                format_decimal(pc_payload.get_quantity(), pc_payload.get_scale()),
+                pc_payload.get_identifier(),
+                pc_payload.get_destination()
+            );
+        }
+        Command::Reserve => {
+            println!(
+                "Reserving {} quantity of {} under {}",
+                format_decimal(pc_payload.get_quantity(), pc_payload.get_scale()),
+                pc_payload.get_identifier(),
+                pc_payload.get_reservation()
+            );
+        }
+        Command::Release => {
+            println!(
+                "Releasing the reservation {} of {}",
+                pc_payload.get_reservation(),
                 pc_payload.get_identifier()
             );
         }
-        Action_Command::CONSUME => {
+        Command::Commit => {
             println!(
-                "Consuming {} quantity of {}",
-                pc_payload.get_quantity(),
+                "Committing the reservation {} of {}",
+                pc_payload.get_reservation(),
                 pc_payload.get_identifier()
             );
         }
+        Command::CreateItem => {
+            println!(
+                "Registering {} measured in {}",
+                pc_payload.get_identifier(),
+                pc_payload.get_unit()
+            );
+        }
+        Command::Multi => {
+            for operation in pc_payload.get_operations() {
+                print_payload(operation);
+            }
+        }
     }
+}
 
-    let payload_bytes = match payload.write_to_bytes() {
-        Ok(bytes) => bytes,
-        Err(err) => return Err(PCError::from(err.to_string())),
-    };
-    let input = compute_address(&pc_payload.get_identifier());
-    let output = compute_address(&pc_payload.get_identifier());
-    Ok((payload_bytes, vec![input], vec![output]))
+fn item_address(owner: Option<&str>, identifier: &str) -> Vec<u8> {
+    hex::decode(compute_address(owner, identifier)).unwrap()
 }
 
-fn compute_address(identifier: &str) -> Vec<u8> {
-    let mut sha = Sha512::default();
-    sha.input(identifier);
-    hex::decode("ce2292".to_owned() + &hex::encode(&sha.result())[..64]).unwrap()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batcher::Batcher;
+    use crate::config::{PCConfig, SchedulerMode};
+    use crate::pc_state::PCState;
+    use crate::scheduler::schedule;
+    use crate::state_handler::commit_state;
+    use cylinder::secp256k1::Secp256k1Context;
+    use cylinder::Context;
+    use transact::state::merkle::MerkleRadixTree;
+
+    #[test]
+    fn version_1_payloads_only_touch_known_items() {
+        let state = PCState::new(&PCConfig::in_memory(SchedulerMode::Serial)).unwrap();
+        let statestore = MerkleState::new(state.get_db());
+        let genesis_root = MerkleRadixTree::new(state.get_db(), None)
+            .unwrap()
+            .get_merkle_root();
+        let context = Secp256k1Context::new();
+        let signer = context.new_signer(context.new_random_private_key());
+        let batcher = Batcher::new(signer, VERSION_2);
+        let (payload, inputs, outputs) = transaction_payload(
+            "CREATE_ITEM apple kg",
+            None,
+            VERSION_2,
+            false,
+            &statestore,
+            &genesis_root,
+        )
+        .unwrap();
+        let batch = batcher.single_txn(&payload, inputs, outputs).unwrap();
+        let result = schedule(&state, vec![batch], &genesis_root)
+            .unwrap()
+            .pop()
+            .unwrap();
+        let state_root = commit_state(&statestore, &genesis_root, result).unwrap();
+
+        let payload =
+            |line: &str| transaction_payload(line, None, VERSION, false, &statestore, &state_root);
+        assert!(payload("PRODUCE apple 4").is_ok());
+        let err = payload("PRODUCE apple 4; PRODUCE pear 1").unwrap_err();
+        assert!(err.to_string().contains("Unknown item pear"));
+        assert!(payload("TRANSFER apple pear 1").is_err());
+    }
 }